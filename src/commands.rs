@@ -0,0 +1,948 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use rpkg::debversion;
+use rpkg::packages::{default_cache_dir, AlternativePreference, ChecksumServer, Packages, ServeOptions, ServerAuth, VerifyProgress};
+use rpkg::packages::checksums::ChecksumType;
+use rpkg::packages::results::{VerificationReport, VerificationResult, VerificationSummary};
+
+use crate::output::{CommandResult, ErrorResult, OutputFormat, PackageCount, ResultsSaved, ServingChecksums, VersionComparison};
+
+/// What happened when a command was processed; in batch mode a Failed command stops the run (unless --keep-going)
+/// and makes the process exit with a non-zero status.
+#[derive(PartialEq)]
+pub enum CommandStatus {
+    Succeeded,
+    Failed,
+    Quit,
+}
+
+impl From<bool> for CommandStatus {
+    fn from(succeeded: bool) -> Self {
+        if succeeded { CommandStatus::Succeeded } else { CommandStatus::Failed }
+    }
+}
+
+/// What the commands work on: the package database, how their results are printed,
+/// the results of the last verification requests executed, and the checksum server started by serve-checksums
+/// with whether it should outlive the input of a batch run.
+pub struct State {
+    pub packages : Packages,
+    pub output_format : OutputFormat,
+    pub last_results : Vec<VerificationResult>,
+    pub checksum_server : Option<ChecksumServer>,
+    keep_serving : bool,
+    // whether requests are being sent in the foreground, for Ctrl-C to cancel
+    foreground : Arc<AtomicBool>,
+}
+
+impl State {
+    /// Also makes Ctrl-C cancel the requests being sent in the foreground; at any other time, it exits as usual.
+    pub fn new(output_format: OutputFormat) -> State {
+        let mut packages = Packages::new();
+        packages.set_cache_dir(default_cache_dir());
+        let foreground = Arc::new(AtomicBool::new(false));
+        let (interrupt, in_foreground) = (packages.interrupt_flag(), Arc::clone(&foreground));
+        let handler = ctrlc::set_handler(move || {
+            if in_foreground.load(Ordering::Relaxed) {
+                interrupt.store(true, Ordering::Relaxed);
+            } else {
+                std::process::exit(130);
+            }
+        });
+        if let Err(e) = handler {
+            eprintln!("Ctrl-C won't cancel requests: {}", e);
+        }
+        State { packages, output_format, last_results: vec![], checksum_server: None, keep_serving: false, foreground }
+    }
+
+    /// Runs f, which sends verification requests, with Ctrl-C cancelling them.
+    fn in_foreground<T>(&mut self, f: impl FnOnce(&mut Packages) -> T) -> T {
+        self.foreground.store(true, Ordering::Relaxed);
+        let result = f(&mut self.packages);
+        self.foreground.store(false, Ordering::Relaxed);
+        result
+    }
+
+    /// Prints result, or the error, in the current output format.
+    pub fn emit<T: CommandResult>(&self, result: Result<T, String>) -> CommandStatus {
+        match result {
+            Ok(result) => { result.print(self.output_format); result.succeeded().into() }
+            Err(error) => { ErrorResult { error }.print(self.output_format); CommandStatus::Failed }
+        }
+    }
+
+    /// Executes any enqueued verification requests and prints the results, if there were any;
+    /// they are kept for last-results.
+    pub fn execute(&mut self) -> CommandStatus {
+        let results = self.in_foreground(Packages::execute);
+        if results.is_empty() {
+            return CommandStatus::Succeeded;
+        }
+        self.last_results = results.clone();
+        self.emit(Ok(VerificationReport { results }))
+    }
+
+    /// Runs one of the bulk verifications with a progress line, prints the summary and keeps the results for last-results.
+    fn verify(&mut self, verify: impl FnOnce(&mut Packages, &mut dyn FnMut(&VerifyProgress)) -> Result<VerificationSummary, String>) -> CommandStatus {
+        let mut progress_line = ProgressLine::new();
+        let summary = self.in_foreground(|packages| verify(packages, &mut |progress| progress_line.update(progress)));
+        progress_line.clear();
+        if let Ok(summary) = &summary {
+            self.last_results = summary.results.clone();
+        }
+        self.emit(summary)
+    }
+
+    /// Waits for background job id, or all of them, with a progress line, and prints their results, if there were any;
+    /// they are kept for last-results.
+    pub fn wait(&mut self, id: Option<usize>) -> CommandStatus {
+        let mut progress_line = ProgressLine::new();
+        let results = self.in_foreground(|packages| packages.wait_jobs(id, &mut |progress| progress_line.update(progress)));
+        progress_line.clear();
+        match results {
+            Ok(results) if results.is_empty() => CommandStatus::Succeeded,
+            Ok(results) => {
+                self.last_results = results.clone();
+                self.emit(Ok(VerificationReport { results }))
+            }
+            Err(e) => self.emit::<ErrorResult>(Err(e))
+        }
+    }
+
+    /// Prints the last verification results again, or writes them to a CSV file.
+    fn last_results(&self, csv_file: Option<&str>) -> CommandStatus {
+        if self.last_results.is_empty() {
+            return self.emit::<ErrorResult>(Err(String::from("no verification requests have been executed")));
+        }
+        let csv_file = match csv_file {
+            None => {
+                // showing mismatches again isn't a failure
+                self.emit(Ok(VerificationReport { results: self.last_results.clone() }));
+                return CommandStatus::Succeeded;
+            }
+            Some(csv_file) => csv_file
+        };
+        let written = csv::Writer::from_path(csv_file).and_then(|mut writer| {
+            self.last_results.iter().try_for_each(|result| writer.serialize(result))?;
+            writer.flush().map_err(csv::Error::from)
+        });
+        let saved = written.map(|_| ResultsSaved { file: String::from(csv_file), saved: self.last_results.len() });
+        self.emit(saved.map_err(|e| format!("couldn't write {}: {}", csv_file, e)))
+    }
+
+    /// Starts serving checksums in the background, from the loaded packages or the CSV file given with --csv,
+    /// and points the verifier at the server.
+    fn serve_checksums(&mut self, args: &Args) -> CommandStatus {
+        if let Some(server) = &self.checksum_server {
+            return self.emit::<ErrorResult>(Err(format!("already serving on {}; stop-serving first", server.addr())));
+        }
+        let mut options = ServeOptions::default();
+        let mut parse_options = || -> Result<(), String> {
+            if let Some(value) = args.get_named("--latency") { options.latency = parse_seconds("--latency", value)?; }
+            if let Some(value) = args.get_named("--jitter") { options.jitter = parse_seconds("--jitter", value)?; }
+            if let Some(value) = args.get_named("--error-rate") { options.error_rate = parse_fraction("--error-rate", value)?; }
+            if let Some(value) = args.get_named("--error-status") {
+                options.error_status = match value.parse() {
+                    Ok(status) if (100..600).contains(&status) => status,
+                    _ => return Err(format!("bad --error-status: expected an HTTP status, not {:?}", value))
+                };
+            }
+            if let Some(value) = args.get_named("--wrong-hash-rate") { options.wrong_hash_rate = parse_fraction("--wrong-hash-rate", value)?; }
+            if let Some(value) = args.get_named("--seed") {
+                options.seed = value.parse().map_err(|e| format!("bad --seed: {}", e))?;
+            }
+            Ok(())
+        };
+        if let Err(e) = parse_options() {
+            return self.emit::<ErrorResult>(Err(e));
+        }
+        let server = match args.get_named("--csv") {
+            None => self.packages.serve_checksums(args.get(0), options),
+            Some(csv_file) => {
+                let mut packages = Packages::new();
+                packages.parse_csv(csv_file).and_then(|_| packages.serve_checksums(args.get(0), options))
+            }
+        };
+        let serving = server.map(|server| {
+            let serving = ServingChecksums { addr: server.addr(), checksums: server.checksums() };
+            self.packages.set_server(&server.addr());
+            self.checksum_server = Some(server);
+            self.keep_serving = args.has_flag("--keep-serving");
+            serving
+        });
+        self.emit(serving)
+    }
+
+    /// At the end of a batch run: keeps serving checksums until the process is killed if serve-checksums was
+    /// given --keep-serving, and otherwise stops serving.
+    pub fn wait_for_checksum_server(&mut self) {
+        match self.checksum_server.take() {
+            Some(server) if self.keep_serving => server.wait(),
+            _ => ()
+        }
+    }
+
+    /// Changes the parts of the verification policy given in args, and prints the resulting policy.
+    fn set_verify_policy(&mut self, args: &Args) -> CommandStatus {
+        let mut policy = self.packages.verify_policy().clone();
+        let mut update = || -> Result<(), String> {
+            if let Some(value) = args.get_named("--connect-timeout") { policy.connect_timeout = parse_seconds("--connect-timeout", value)?; }
+            if let Some(value) = args.get_named("--timeout") { policy.timeout = parse_seconds("--timeout", value)?; }
+            if let Some(value) = args.get_named("--retries") {
+                policy.max_retries = value.parse().map_err(|e| format!("bad --retries: {}", e))?;
+            }
+            if let Some(value) = args.get_named("--backoff") { policy.backoff = parse_seconds("--backoff", value)?; }
+            if let Some(value) = args.get_named("--max-backoff") { policy.max_backoff = parse_seconds("--max-backoff", value)?; }
+            if let Some(value) = args.get_named("--max-in-flight") {
+                policy.max_in_flight = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("bad --max-in-flight: expected a positive number, not {:?}", value))
+                };
+            }
+            if let Some(value) = args.get_named("--rate") {
+                policy.max_rate = match value.parse::<f64>() {
+                    Ok(0.0) => None,
+                    Ok(rate) if rate.is_finite() && rate > 0.0 => Some(rate),
+                    _ => return Err(format!("bad --rate: expected a number of requests per second, not {:?}", value))
+                };
+            }
+            if let Some(value) = args.get_named("--keep-alive") {
+                policy.keep_alive = match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(format!("--keep-alive must be yes or no, not {:?}", value))
+                };
+            }
+            if let Some(value) = args.get_named("--cache-ttl") { policy.cache_ttl = parse_seconds("--cache-ttl", value)?; }
+            if let Some(value) = args.get_named("--ca-bundle") {
+                policy.ca_bundle = match value {
+                    "default" => None,
+                    _ if std::path::Path::new(value).is_file() => Some(String::from(value)),
+                    _ => return Err(format!("bad --ca-bundle: no such file {}", value))
+                };
+            }
+            if let Some(value) = args.get_named("--proxy") {
+                policy.proxy = match value {
+                    "env" => None,
+                    "none" => Some(String::new()),
+                    _ => Some(String::from(value))
+                };
+            }
+            if let Some(value) = args.get_named("--no-proxy") {
+                policy.no_proxy = if value == "env" { None } else { Some(String::from(value)) };
+            }
+            Ok(())
+        };
+        if let Err(e) = update() {
+            return self.emit::<ErrorResult>(Err(e));
+        }
+        self.packages.set_verify_policy(policy);
+        self.emit(Ok(self.packages.verify_policy().clone()))
+    }
+}
+
+/// A line on stderr showing how far a verification has got, redrawn at most 10 times a second;
+/// there's none if stderr isn't a terminal, so that logs of batch runs stay clean.
+struct ProgressLine {
+    enabled : bool,
+    last_drawn : Option<Instant>,
+}
+
+impl ProgressLine {
+    fn new() -> ProgressLine {
+        ProgressLine { enabled: std::io::stderr().is_terminal(), last_drawn: None }
+    }
+
+    fn update(&mut self, progress: &VerifyProgress) {
+        let due = self.last_drawn.is_none_or(|t| t.elapsed() >= Duration::from_millis(100));
+        if !self.enabled || !(due || progress.done == progress.total) {
+            return;
+        }
+        eprint!("\r\x1b[K{}/{} verified, {} mismatches, {} errors, {:.1} requests/s",
+                progress.done, progress.total, progress.mismatches, progress.errors, progress.rate());
+        std::io::stderr().flush().ok();
+        self.last_drawn = Some(Instant::now());
+    }
+
+    fn clear(&self) {
+        if self.last_drawn.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+fn parse_seconds(flag: &str, value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("bad {}: expected a number of seconds, not {:?}", flag, value))
+    }
+}
+
+/// The credentials for set-server-auth; a $NAME credential is read from the environment.
+fn parse_server_auth(kind: &str, credentials: Option<&str>) -> Result<Option<ServerAuth>, String> {
+    let credentials = match credentials.map(|c| (c, c.strip_prefix('$'))) {
+        Some((_, Some(name))) => Some(std::env::var(name).map_err(|_| format!("environment variable {} isn't set", name))?),
+        Some((c, None)) => Some(String::from(c)),
+        None => None
+    };
+    match (kind, credentials) {
+        ("none", None) => Ok(None),
+        ("basic", Some(credentials)) => match credentials.split_once(':') {
+            Some((user, password)) => Ok(Some(ServerAuth::Basic { user: String::from(user), password: String::from(password) })),
+            None => Err(String::from("basic auth takes user:password"))
+        },
+        ("bearer", Some(token)) => Ok(Some(ServerAuth::Bearer { token })),
+        ("none", Some(_)) => Err(String::from("none takes no credentials")),
+        ("basic", None) | ("bearer", None) => Err(format!("{} auth needs credentials", kind)),
+        _ => Err(format!("unknown auth {:?}: expected basic, bearer or none", kind))
+    }
+}
+
+/// A job number, as listed by jobs.
+fn parse_job(value: Option<&str>) -> Result<Option<usize>, String> {
+    value.map(|value| value.parse().map_err(|_| format!("bad job {:?}: expected a job number, as listed by jobs", value))).transpose()
+}
+
+fn parse_fraction(flag: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("bad {}: expected a number from 0 to 1, not {:?}", flag, value))
+    }
+}
+
+// the loaders report how many packages there are, as plain text whatever the output format
+fn report_count(state: &State, count: Result<usize, String>, what: &str) -> CommandStatus {
+    state.emit(count.map(|packages| PackageCount { what: String::from(what), packages }))
+}
+
+/// What an argument is, so that the REPL knows how to complete it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Package,
+    File,
+    Other,
+}
+
+pub enum ArgSpec {
+    /// a positional argument that must be present
+    Required(&'static str, ArgKind),
+    /// a positional argument that may be left out; these come after the required ones
+    Optional(&'static str, ArgKind),
+    /// one or more positional arguments, as the last positional ArgSpec
+    Repeated(&'static str, ArgKind),
+    /// an optional flag taking a value, e.g. --only <regex>, which may appear anywhere
+    Named(&'static str, &'static str, ArgKind),
+    /// an optional flag taking no value, e.g. --no-cache, which may appear anywhere
+    Flag(&'static str),
+}
+
+/// The arguments of a command, after they have been checked against its ArgSpecs.
+pub struct Args {
+    positional : Vec<String>,
+    named : HashMap<&'static str, String>,
+    flags : HashSet<&'static str>,
+}
+
+impl Args {
+    /// Returns positional argument i; panics if the spec doesn't guarantee it's there.
+    pub fn get(&self, i: usize) -> &str {
+        &self.positional[i]
+    }
+
+    pub fn get_optional(&self, i: usize) -> Option<&str> {
+        self.positional.get(i).map(|a| &a[..])
+    }
+
+    /// Returns positional arguments i and onwards, for a Repeated argument.
+    pub fn get_rest(&self, i: usize) -> Vec<&str> {
+        self.positional[i..].iter().map(|a| &a[..]).collect()
+    }
+
+    pub fn get_named(&self, flag: &str) -> Option<&str> {
+        self.named.get(flag).map(|a| &a[..])
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+}
+
+pub struct Command {
+    pub name : &'static str,
+    pub aliases : &'static [&'static str],
+    pub args : &'static [ArgSpec],
+    pub help : &'static str,
+    pub run : fn(&mut State, &Args) -> CommandStatus,
+}
+
+impl Command {
+    pub fn syntax(&self) -> String {
+        let mut syntax = String::from(self.name);
+        for arg in self.args {
+            let a = match arg {
+                ArgSpec::Required(name, _) => name.to_string(),
+                ArgSpec::Optional(name, _) => format!("[{}]", name),
+                ArgSpec::Repeated(name, _) => format!("{}...", name),
+                ArgSpec::Named(flag, value, _) => format!("[{} {}]", flag, value),
+                ArgSpec::Flag(flag) => format!("[{}]", flag)
+            };
+            syntax.push(' ');
+            syntax.push_str(&a);
+        }
+        syntax
+    }
+
+    /// Returns the kind of the argument that would follow words (not including the command name), if any.
+    pub fn next_arg_kind(&self, words: &[&str]) -> Option<ArgKind> {
+        let mut positional = 0;
+        let mut words = words.iter();
+        while let Some(word) = words.next() {
+            if self.has_flag(word) {
+                continue;
+            }
+            let named = self.args.iter().find_map(|arg| match arg {
+                ArgSpec::Named(flag, _, kind) if flag == word => Some(*kind),
+                _ => None
+            });
+            match named {
+                Some(kind) => if words.next().is_none() { return Some(kind) },
+                None => positional += 1
+            }
+        }
+        let mut positional_kinds = self.args.iter().filter_map(|arg| match arg {
+            ArgSpec::Required(_, kind) | ArgSpec::Optional(_, kind) | ArgSpec::Repeated(_, kind) => Some(*kind),
+            ArgSpec::Named(..) | ArgSpec::Flag(..) => None
+        });
+        let repeated = self.args.iter().find_map(|arg| match arg {
+            ArgSpec::Repeated(_, kind) => Some(*kind),
+            _ => None
+        });
+        positional_kinds.nth(positional).or(repeated)
+    }
+
+    fn has_flag(&self, word: &str) -> bool {
+        self.args.iter().any(|arg| matches!(arg, ArgSpec::Flag(flag) if *flag == word))
+    }
+
+    /// Checks words (not including the command name) against the command's ArgSpecs.
+    fn parse_args(&self, words: &[String]) -> Option<Args> {
+        let mut args = Args { positional: vec![], named: HashMap::new(), flags: HashSet::new() };
+        let mut words = words.iter();
+        while let Some(word) = words.next() {
+            if let Some(flag) = self.args.iter().find_map(|arg| match arg {
+                ArgSpec::Flag(flag) if flag == word => Some(*flag),
+                _ => None
+            }) {
+                args.flags.insert(flag);
+                continue;
+            }
+            let named = self.args.iter().find_map(|arg| match arg {
+                ArgSpec::Named(flag, _, _) if flag == word => Some(*flag),
+                _ => None
+            });
+            match named {
+                Some(flag) => { args.named.insert(flag, words.next()?.clone()); }
+                None => args.positional.push(word.clone())
+            }
+        }
+        let required = self.args.iter().filter(|arg| matches!(arg, ArgSpec::Required(..))).count();
+        let optional = self.args.iter().filter(|arg| matches!(arg, ArgSpec::Optional(..))).count();
+        let repeated = self.args.iter().any(|arg| matches!(arg, ArgSpec::Repeated(..)));
+        if args.positional.len() < required + repeated as usize || (!repeated && args.positional.len() > required + optional) {
+            return None;
+        }
+        Some(args)
+    }
+}
+
+pub const COMMANDS : &[Command] = &[
+    Command {
+        name: "help", aliases: &[], args: &[ArgSpec::Optional("<cmd>", ArgKind::Other)],
+        help: "Lists the commands, or describes one command in detail.",
+        run: |_, args| print_help(args.get_optional(0)).into(),
+    },
+    Command {
+        name: "quit", aliases: &[], args: &[],
+        help: "Executes any enqueued verification requests and quits.",
+        run: |_, _| CommandStatus::Quit,
+    },
+    Command {
+        name: "load-csv", aliases: &["lc"], args: &[ArgSpec::Required("<csvfile-name>", ArgKind::File)],
+        help: "Loads available packages, versions and checksums from a name,version,hash CSV file, as written by output-checksums; \
+               the hash column is the MD5sum, and sha256 and sha512 columns give those checksums.",
+        run: |state, args| { let count = state.packages.parse_csv(args.get(0)); report_count(state, count, "available") },
+    },
+    // parsers.rs
+    Command {
+        name: "load-packages", aliases: &["lp"], args: &[ArgSpec::Required("<pkgfile-name>", ArgKind::File)],
+        help: "Loads available packages from a Debian Packages index.",
+        run: |state, args| { let count = state.packages.parse_packages(args.get(0)); report_count(state, count, "available") },
+    },
+    Command {
+        name: "load-installed", aliases: &["li"], args: &[ArgSpec::Required("<pkgfile-name>", ArgKind::File)],
+        help: "Loads installed packages from a dpkg status file.",
+        run: |state, args| { let count = state.packages.parse_installed(args.get(0)); report_count(state, count, "installed") },
+    },
+    // convenience function, also depends on parsers.rs
+    Command {
+        name: "load-defaults", aliases: &["ld"], args: &[],
+        help: "Loads the provided available and installed packages from the data directory.",
+        run: |state, _| {
+            let count = state.packages.parse_packages("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages");
+            let packages_loaded = report_count(state, count, "available");
+            let count = state.packages.parse_installed("data/installed-packages");
+            let installed_loaded = report_count(state, count, "installed");
+            (packages_loaded == CommandStatus::Succeeded && installed_loaded == CommandStatus::Succeeded).into()
+        },
+    },
+    // cache.rs
+    Command {
+        name: "cache-info", aliases: &[], args: &[],
+        help: "Lists the cached parsed Packages and status files, and whether each is still valid.",
+        run: |state, _| state.emit(Ok(state.packages.cache_info())),
+    },
+    Command {
+        name: "cache-clear", aliases: &[], args: &[],
+        help: "Removes all the cached parsed files.",
+        run: |state, _| state.emit(state.packages.cache_clear()),
+    },
+    Command {
+        name: "save-installed", aliases: &[], args: &[ArgSpec::Required("<output-file>", ArgKind::File)],
+        help: "Writes the installed packages out in dpkg status format.",
+        run: |state, args| { let count = state.packages.save_installed(args.get(0)); report_count(state, count, "saved") },
+    },
+
+    Command {
+        name: "info", aliases: &[], args: &[ArgSpec::Required("<pkg>", ArgKind::Package)],
+        help: "Prints everything known about a package, integrating available and installed information.",
+        run: |state, args| state.emit(state.packages.info(args.get(0))),
+    },
+    Command {
+        // test: deps 0ad
+        name: "deps", aliases: &[], args: &[ArgSpec::Required("<pkg>", ArgKind::Package)],
+        help: "Prints the dependencies of a package.",
+        run: |state, args| state.emit(state.packages.deps(args.get(0))),
+    },
+    // deps-available.rs
+    Command {
+        // test: deps-available 3depict
+        name: "deps-available", aliases: &[], args: &[ArgSpec::Required("<pkg>", ArgKind::Package)],
+        help: "Prints whether each dependency of a package is satisfied by an installed package.",
+        run: |state, args| state.emit(state.packages.deps_available(args.get(0))),
+    },
+    Command {
+        name: "check", aliases: &[], args: &[],
+        help: "Checks the dependencies and conflicts of every installed package against the installed set.",
+        run: |state, _| state.emit(Ok(state.packages.check())),
+    },
+    // installability.rs
+    Command {
+        name: "check-installability", aliases: &[], args: &[ArgSpec::Named("--only", "<regex>", ArgKind::Other)],
+        help: "Reports the available packages that can't be installed from the loaded repository, and why.",
+        run: |state, args| state.emit(state.packages.check_installability(args.get_named("--only"))),
+    },
+    // diff.rs
+    Command {
+        name: "diff-packages", aliases: &[],
+        args: &[ArgSpec::Required("<old-pkgfile>", ArgKind::File), ArgSpec::Required("<new-pkgfile>", ArgKind::File)],
+        help: "Compares two Packages indices (leaving the loaded packages alone): lists the added and removed packages, the version changes \
+               (by whether the epoch, upstream version or Debian revision changed) and the changed dependencies.",
+        run: |state, args| state.emit(state.packages.diff_packages(args.get(0), args.get(1))),
+    },
+    // search.rs
+    Command {
+        // test: search installed:yes upgradable:yes depends:libssl*
+        name: "search", aliases: &[],
+        args: &[ArgSpec::Repeated("<term>", ArgKind::Other), ArgSpec::Named("--sort", "<[-]name|version|installed>", ArgKind::Other),
+                ArgSpec::Named("--limit", "<n>", ArgKind::Other)],
+        help: "Lists the packages matching all the terms: a regex matches names and synopses, name:<regex> and description:<regex> \
+               just one of them; section:<glob>, priority:<priority>, and installed:, available: and upgradable: with yes or no \
+               match those fields; depends:<glob> and provides:<glob> match the names of dependencies and provides \
+               of the available or installed version.",
+        run: |state, args| match args.get_named("--limit").map(str::parse::<usize>).transpose() {
+            Ok(limit) => state.emit(state.packages.search(&args.get_rest(0), args.get_named("--sort"), limit)),
+            Err(e) => state.emit::<ErrorResult>(Err(format!("bad limit: {}", e)))
+        },
+    },
+    // solvers.rs, and deps-available.rs for how-to-install
+    Command {
+        // test: transitive-dep-solution 0ad
+        name: "transitive-dep-solution", aliases: &[], args: &[ArgSpec::Required("<pkg>", ArgKind::Package)],
+        help: "Prints the unversioned transitive dependencies of a package, choosing the first of any alternatives.",
+        run: |state, args| state.emit(state.packages.transitive_dep_solution(args.get(0))),
+    },
+    Command {
+        name: "how-to-install", aliases: &[],
+        args: &[ArgSpec::Required("<pkg>", ArgKind::Package), ArgSpec::Named("--prefer", "<highest-version|smallest-footprint>", ArgKind::Other)],
+        help: "Prints the packages that would need to be installed to install a package, with their download and installed sizes; \
+               --prefer says how to choose among alternatives that aren't installed.",
+        run: |state, args| match args.get_named("--prefer").map(str::parse::<AlternativePreference>).transpose() {
+            Ok(preference) => state.emit(state.packages.how_to_install(args.get(0), preference.unwrap_or(AlternativePreference::HighestVersion))),
+            Err(e) => state.emit::<ErrorResult>(Err(e))
+        },
+    },
+    Command {
+        name: "simulate-install", aliases: &[],
+        args: &[ArgSpec::Required("<pkg>", ArgKind::Package), ArgSpec::Named("--prefer", "<highest-version|smallest-footprint>", ArgKind::Other)],
+        help: "Records a package and the packages how-to-install would install with it as installed at their available versions, \
+               in memory only, and prints the plan.",
+        run: |state, args| match args.get_named("--prefer").map(str::parse::<AlternativePreference>).transpose() {
+            Ok(preference) => {
+                let plan = state.packages.simulate_install(args.get(0), preference.unwrap_or(AlternativePreference::HighestVersion));
+                state.emit(plan)
+            }
+            Err(e) => state.emit::<ErrorResult>(Err(e))
+        },
+    },
+
+    Command {
+        name: "set-format", aliases: &[], args: &[ArgSpec::Required("<text|json>", ArgKind::Other)],
+        help: "Sets whether results are printed as text or as JSON.",
+        run: |state, args| match args.get(0).parse::<OutputFormat>() {
+            Ok(output_format) => { state.output_format = output_format; CommandStatus::Succeeded }
+            Err(e) => state.emit::<ErrorResult>(Err(e))
+        },
+    },
+    // async_fns.rs
+    Command {
+        name: "set-server", aliases: &[], args: &[ArgSpec::Repeated("<server>", ArgKind::Other)],
+        help: "Sets the host:port of the checksum server used by enq-verify, or of several mirrors in order of preference: \
+               requests go to the first one that isn't down and are retried against the next ones. A server given as \
+               https://host:port is reached over HTTPS.",
+        run: |state, args| { state.packages.set_servers(&args.get_rest(0)); CommandStatus::Succeeded },
+    },
+    Command {
+        name: "set-server-auth", aliases: &[],
+        args: &[ArgSpec::Required("<server>", ArgKind::Other), ArgSpec::Required("<basic|bearer|none>", ArgKind::Other),
+                ArgSpec::Optional("<user:password|token>", ArgKind::Other)],
+        help: "Sets the credentials sent to a checksum server, as given to set-server: HTTP basic auth with a user and \
+               password, or a bearer token sent in an Authorization header. $NAME stands for the value of the environment variable \
+               NAME, which keeps secrets out of scripts and the history.",
+        run: |state, args| match parse_server_auth(args.get(1), args.get_optional(2)) {
+            Ok(auth) => { state.packages.set_server_auth(args.get(0), auth); CommandStatus::Succeeded }
+            Err(e) => state.emit::<ErrorResult>(Err(e))
+        },
+    },
+    Command {
+        name: "servers", aliases: &[], args: &[],
+        help: "Lists the checksum servers with whether they are up, how many of their requests succeeded and their mean latency.",
+        run: |state, _| state.emit(Ok(state.packages.servers())),
+    },
+    Command {
+        name: "set-verify-policy", aliases: &[],
+        args: &[ArgSpec::Named("--connect-timeout", "<secs>", ArgKind::Other), ArgSpec::Named("--timeout", "<secs>", ArgKind::Other),
+                ArgSpec::Named("--retries", "<n>", ArgKind::Other), ArgSpec::Named("--backoff", "<secs>", ArgKind::Other),
+                ArgSpec::Named("--max-backoff", "<secs>", ArgKind::Other), ArgSpec::Named("--max-in-flight", "<n>", ArgKind::Other),
+                ArgSpec::Named("--rate", "<requests/s>", ArgKind::Other), ArgSpec::Named("--keep-alive", "<yes|no>", ArgKind::Other),
+                ArgSpec::Named("--cache-ttl", "<secs>", ArgKind::Other), ArgSpec::Named("--ca-bundle", "<pem-file|default>", ArgKind::File),
+                ArgSpec::Named("--proxy", "<url|env|none>", ArgKind::Other), ArgSpec::Named("--no-proxy", "<hosts|env>", ArgKind::Other)],
+        help: "Sets the timeouts of verification requests (0 for none) and how often requests that get no response, \
+               or a 429 or 5xx response, are retried; the n-th retry waits the backoff times 2^(n-1), or what the server's \
+               Retry-After says, up to the max backoff. Also sets how many requests may be outstanding at once, how many \
+               may be started per second (0 for no limit), whether connections are reused and how long the hashes received \
+               are cached (0 for not at all). For HTTPS servers, a CA bundle can replace the system's CA certificates. \
+               The proxy and the comma-separated hosts reached without it come from http_proxy, https_proxy and \
+               no_proxy unless given. Prints the resulting policy.",
+        run: |state, args| state.set_verify_policy(args),
+    },
+    Command {
+        name: "enq-verify", aliases: &[],
+        args: &[ArgSpec::Required("<pkg>", ArgKind::Package), ArgSpec::Optional("<version>", ArgKind::Other), ArgSpec::Flag("--no-cache")],
+        help: "Enqueues a request for the MD5sum of a package, at the available version unless a version is given. \
+               A fresh hash in the verification cache is used instead of a request, unless --no-cache is given.",
+        run: |state, args| {
+            let use_cache = !args.has_flag("--no-cache");
+            let queued = match args.get_optional(1) {
+                None => state.packages.enq_verify(args.get(0), use_cache),
+                Some(version) => state.packages.enq_verify_with_version(args.get(0), version, use_cache)
+            };
+            state.emit(queued)
+        },
+    },
+    Command {
+        name: "execute", aliases: &[], args: &[ArgSpec::Flag("--background")],
+        help: "Executes all enqueued requests and compares the returned MD5sums with the local ones. Ctrl-C cancels the \
+               requests that haven't been answered yet. With --background, the requests are sent while other commands \
+               run, as a job whose results wait prints.",
+        run: |state, args| {
+            if !args.has_flag("--background") {
+                return state.execute();
+            }
+            let job = state.packages.execute_in_background();
+            state.emit(job)
+        },
+    },
+    Command {
+        name: "jobs", aliases: &[], args: &[],
+        help: "Lists the background jobs started by execute --background that haven't been waited for, with their progress.",
+        run: |state, _| state.emit(Ok(state.packages.jobs())),
+    },
+    Command {
+        name: "wait", aliases: &[], args: &[ArgSpec::Optional("<job>", ArgKind::Other)],
+        help: "Waits for a background job, or all of them, showing progress, then prints their results like execute. \
+               Ctrl-C cancels the jobs being waited for.",
+        run: |state, args| match parse_job(args.get_optional(0)) {
+            Ok(id) => state.wait(id),
+            Err(e) => state.emit::<ErrorResult>(Err(e))
+        },
+    },
+    Command {
+        name: "cancel", aliases: &[], args: &[ArgSpec::Optional("<job>", ArgKind::Other)],
+        help: "Cancels a background job, or all of them: requests that haven't been answered yet are dropped. \
+               Their results are still printed by wait.",
+        run: |state, args| {
+            let cancelled = parse_job(args.get_optional(0)).and_then(|id| state.packages.cancel_jobs(id));
+            state.emit(cancelled)
+        },
+    },
+    Command {
+        name: "verify-installed", aliases: &[], args: &[ArgSpec::Flag("--no-cache")],
+        help: "Verifies the MD5sums of all installed packages at their installed versions, along with any enqueued requests, \
+               showing progress, then lists the requests that didn't match and a summary. Packages installed at another version \
+               than the available one are skipped, since only the checksums of available versions are known. Like enq-verify, \
+               uses the verification cache unless --no-cache is given.",
+        run: |state, args| state.verify(|packages, progress| packages.verify_installed(!args.has_flag("--no-cache"), progress)),
+    },
+    Command {
+        name: "verify-all", aliases: &[], args: &[ArgSpec::Flag("--no-cache")],
+        help: "Like verify-installed, for all available packages at their available versions.",
+        run: |state, args| state.verify(|packages, progress| packages.verify_all(!args.has_flag("--no-cache"), progress)),
+    },
+    Command {
+        name: "verify-matching", aliases: &[], args: &[ArgSpec::Required("<regex>", ArgKind::Other), ArgSpec::Flag("--no-cache")],
+        help: "Like verify-all, for the available packages whose name matches a regex.",
+        run: |state, args| state.verify(|packages, progress| packages.verify_matching(args.get(0), !args.has_flag("--no-cache"), progress)),
+    },
+    Command {
+        name: "verify-cache", aliases: &[], args: &[ArgSpec::Required("<stats|clear>", ArgKind::Other)],
+        help: "Shows how many hashes are in the verification cache, which keeps the hashes received from checksum servers \
+               across sessions, and how many are still fresh; or clears it.",
+        run: |state, args| match args.get(0) {
+            "stats" => { let stats = state.packages.verify_cache_stats(); state.emit(Ok(stats)) }
+            "clear" => { let cleared = state.packages.verify_cache_clear(); state.emit(cleared) }
+            other => state.emit::<ErrorResult>(Err(format!("verify-cache takes stats or clear, not {:?}", other)))
+        },
+    },
+    Command {
+        name: "last-results", aliases: &[], args: &[ArgSpec::Optional("<csv-file>", ArgKind::File)],
+        help: "Prints the results of the last executed requests again, or writes them to a CSV file with the URL, \
+               HTTP status, both hashes, outcome and latency of each request.",
+        run: |state, args| state.last_results(args.get_optional(0)),
+    },
+    Command {
+        name: "serve-checksums", aliases: &[],
+        args: &[ArgSpec::Required("<host:port>", ArgKind::Other), ArgSpec::Named("--csv", "<csvfile-name>", ArgKind::File),
+                ArgSpec::Named("--latency", "<secs>", ArgKind::Other), ArgSpec::Named("--jitter", "<secs>", ArgKind::Other),
+                ArgSpec::Named("--error-rate", "<fraction>", ArgKind::Other), ArgSpec::Named("--error-status", "<status>", ArgKind::Other),
+                ArgSpec::Named("--wrong-hash-rate", "<fraction>", ArgKind::Other), ArgSpec::Named("--seed", "<n>", ArgKind::Other),
+                ArgSpec::Flag("--keep-serving")],
+        help: "Serves the checksum API in the background on a local address (port 0 picks one), with the checksums of the \
+               available packages or of a CSV file as written by output-checksums. Responses can be delayed by the latency plus up \
+               to the jitter, and a fraction of them can be errors (500 unless --error-status says otherwise) or wrong hashes, \
+               chosen at random from the seed. Later verifications use the server, unless set-server says otherwise. \
+               With --keep-serving, a batch run that is still serving at the end of its input keeps serving until killed.",
+        run: |state, args| state.serve_checksums(args),
+    },
+    Command {
+        name: "stop-serving", aliases: &[], args: &[],
+        help: "Stops the checksum server started by serve-checksums and prints how its requests were answered.",
+        run: |state, _| match state.checksum_server.take() {
+            None => state.emit::<ErrorResult>(Err(String::from("not serving checksums"))),
+            Some(server) => state.emit(Ok(server.stop()))
+        },
+    },
+    // debs.rs
+    Command {
+        name: "verify-deb", aliases: &[], args: &[ArgSpec::Required("<file>", ArgKind::File)],
+        help: "Hashes a .deb file and compares its size and checksums with those in the loaded Packages index, finding its \
+               package by the Filename field or else by the package and version in its control data.",
+        run: |state, args| state.emit(state.packages.verify_deb(args.get(0))),
+    },
+    Command {
+        name: "verify-pool", aliases: &[], args: &[ArgSpec::Required("<dir>", ArgKind::File)],
+        help: "Like verify-deb, for all .deb files under a directory (the root of an archive or its pool), hashed in \
+               parallel; lists the corrupt and unknown files, and the packages of the index whose file is missing.",
+        run: |state, args| state.emit(state.packages.verify_pool(args.get(0))),
+    },
+
+    Command {
+        name: "output-checksums", aliases: &["output-md5s"],
+        args: &[ArgSpec::Required("<output-file>", ArgKind::File), ArgSpec::Named("--algo", "<md5|sha256|sha512>", ArgKind::Other)],
+        help: "Writes the checksums of all available packages to a CSV file for the package verifier: their MD5sums, \
+               or the checksums of another type for the packages that have one.",
+        run: |state, args| {
+            let written = args.get_named("--algo").map_or(Ok(ChecksumType::Md5), str::parse::<ChecksumType>)
+                .and_then(|algo| state.packages.output_checksums(args.get(0), algo));
+            match written {
+                Ok(_) => CommandStatus::Succeeded,
+                Err(e) => state.emit::<ErrorResult>(Err(e))
+            }
+        },
+    },
+    Command {
+        name: "test-version-compare", aliases: &[], args: &[ArgSpec::Required("<version1>", ArgKind::Other), ArgSpec::Required("<version2>", ArgKind::Other)],
+        help: "Prints how two Debian version numbers compare.",
+        run: |state, args| {
+            let v1 = args.get(0).parse::<debversion::DebianVersionNum>().unwrap();
+            let v2 = args.get(1).parse::<debversion::DebianVersionNum>().unwrap();
+            let comparison = VersionComparison { version1: v1.to_string(), version2: v2.to_string(), ordering: v1.cmp(&v2).into() };
+            // 1:0.4.5+cvs20030824-9 vs 1:0.4.5+cvs20030824-10
+            // a vs b
+            // a vs a
+            // b vs a
+            // 1-a vs 1-b
+            // 2-a vs 1-b
+            // a vs ~a
+            state.emit(Ok(comparison))
+        },
+    },
+];
+
+/// Finds a command by its name or one of its aliases.
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name || c.aliases.contains(&name))
+}
+
+fn print_help(cmd: Option<&str>) -> bool {
+    match cmd {
+        None => {
+            for command in COMMANDS {
+                println!("{:<44} {}", command.syntax(), command.help);
+            }
+            true
+        }
+        Some(name) => match find_command(name) {
+            None => { println!("no such command {:?}", name); false }
+            Some(command) => {
+                println!("syntax: {}", command.syntax());
+                if !command.aliases.is_empty() {
+                    println!("aliases: {}", command.aliases.join(", "));
+                }
+                println!("{}", command.help);
+                true
+            }
+        }
+    }
+}
+
+/// Splits a command line into words like a shell would: words are separated by whitespace, single quotes
+/// preserve everything inside them, double quotes preserve everything but backslash escapes,
+/// and a backslash outside quotes escapes the next character.
+pub fn split_words(cmdline: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word : Option<String> = None;
+    let mut chars = cmdline.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() { words.push(w); }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated single quote")),
+                        Some('\'') => break,
+                        Some(c) => w.push(c)
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        None => return Err(String::from("unterminated double quote")),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            None => return Err(String::from("unterminated double quote")),
+                            Some(c) => w.push(c)
+                        },
+                        Some(c) => w.push(c)
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                None => return Err(String::from("backslash at end of line")),
+                Some(c) => word.get_or_insert_with(String::new).push(c)
+            },
+            c => word.get_or_insert_with(String::new).push(c)
+        }
+    }
+    if let Some(w) = word { words.push(w); }
+    Ok(words)
+}
+
+/// Splits a list of commands on the semicolons that aren't quoted or escaped, leaving the quoting in place.
+pub fn split_commands(cmdlines: &str) -> Vec<String> {
+    let mut commands = vec![];
+    let mut current = String::new();
+    let mut quote : Option<char> = None;
+    let mut escaped = false;
+    for c in cmdlines.chars() {
+        match (c, quote, escaped) {
+            (_, _, true) => escaped = false,
+            ('\\', Some('\''), _) => (),
+            ('\\', _, _) => escaped = true,
+            ('\'', None, _) | ('"', None, _) => quote = Some(c),
+            (c, Some(q), _) if c == q => quote = None,
+            (';', None, _) => { commands.push(std::mem::take(&mut current)); continue; }
+            _ => ()
+        }
+        current.push(c);
+    }
+    commands.push(current);
+    commands
+}
+
+pub fn process_command(state: &mut State, cmdline: &str) -> CommandStatus {
+    let words = match split_words(cmdline) {
+        Ok(words) => words,
+        Err(e) => return state.emit::<ErrorResult>(Err(format!("syntax error: {}", e))),
+    };
+    let (cmd, rest) = match words.split_first() {
+        None => return CommandStatus::Succeeded,
+        Some(split) => split
+    };
+    let command = match find_command(cmd) {
+        None => return state.emit::<ErrorResult>(Err(format!("couldn't understand cmd {:?}; try help", cmd))),
+        Some(command) => command
+    };
+    match command.parse_args(rest) {
+        None => state.emit::<ErrorResult>(Err(format!("syntax: {}", command.syntax()))),
+        Some(args) => (command.run)(state, &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_splits_on_whitespace() {
+        assert_eq!(split_words("  info   zsh ").unwrap(), vec!["info", "zsh"]);
+        assert!(split_words("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_words_handles_quotes_and_escapes() {
+        assert_eq!(split_words("load-packages 'my packages'").unwrap(), vec!["load-packages", "my packages"]);
+        assert_eq!(split_words(r#"search "a \"b\" \\c""#).unwrap(), vec!["search", r#"a "b" \c"#]);
+        assert_eq!(split_words(r"load-packages my\ packages").unwrap(), vec!["load-packages", "my packages"]);
+        // quoted parts join the word around them, and empty quotes make an empty word
+        assert_eq!(split_words("a'b c'd ''").unwrap(), vec!["ab cd", ""]);
+        // no escapes inside single quotes
+        assert_eq!(split_words(r"'a\'").unwrap(), vec![r"a\"]);
+    }
+
+    #[test]
+    fn split_words_rejects_unterminated_quotes() {
+        assert!(split_words("info 'zsh").is_err());
+        assert!(split_words("info \"zsh").is_err());
+        assert!(split_words("info \"zsh\\").is_err());
+        assert!(split_words("info zsh\\").is_err());
+    }
+}
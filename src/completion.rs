@@ -0,0 +1,77 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+use crate::commands::{find_command, ArgKind, COMMANDS};
+use rpkg::packages::Packages;
+
+/// Completes command names, file names and package names in the REPL.
+pub struct RpkgHelper {
+    filename_completer : FilenameCompleter,
+    // sorted, so that all the names with a given prefix are next to each other
+    package_names : Vec<String>,
+}
+
+impl RpkgHelper {
+    pub fn new() -> RpkgHelper {
+        RpkgHelper {
+            filename_completer : FilenameCompleter::new(),
+            package_names : vec![],
+        }
+    }
+
+    /// Rebuilds the package name index if packages were added to state since the last time.
+    pub fn update_package_names(&mut self, state: &Packages) {
+        if state.package_count() == self.package_names.len() {
+            return;
+        }
+        self.package_names = state.get_package_names().into_iter().map(String::from).collect();
+        self.package_names.sort_unstable();
+    }
+
+    fn complete_package_name(&self, prefix: &str) -> Vec<Pair> {
+        let start = self.package_names.partition_point(|name| name.as_str() < prefix);
+        self.package_names[start..].iter()
+            .take_while(|name| name.starts_with(prefix))
+            .map(|name| Pair { display: name.clone(), replacement: name.clone() })
+            .collect()
+    }
+}
+
+impl Completer for RpkgHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let word_start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[word_start..];
+        let preceding : Vec<&str> = line[..word_start].split_whitespace().collect();
+        let (cmd, args) = match preceding.split_first() {
+            None => {
+                let names = COMMANDS.iter().flat_map(|c| std::iter::once(&c.name).chain(c.aliases.iter()));
+                return Ok((word_start, names
+                    .filter(|name| name.starts_with(word))
+                    .map(|name| Pair { display: String::from(*name), replacement: format!("{} ", name) })
+                    .collect()));
+            }
+            Some(split) => split
+        };
+        match find_command(cmd).and_then(|c| c.next_arg_kind(args)) {
+            Some(ArgKind::File) => self.filename_completer.complete(line, pos, ctx),
+            Some(ArgKind::Package) => Ok((word_start, self.complete_package_name(word))),
+            _ => Ok((pos, vec![]))
+        }
+    }
+}
+
+impl Hinter for RpkgHelper {
+    type Hint = String;
+}
+
+impl Highlighter for RpkgHelper {}
+
+impl Validator for RpkgHelper {}
+
+impl Helper for RpkgHelper {}
@@ -3,6 +3,7 @@ use std::string::ParseError;
 use std::cmp::Ordering::{Less, Equal, Greater};
 use std::str::FromStr;
 
+#[derive(Clone)]
 pub enum VersionRelation {
     StrictlyLess, // <<
     LessOrEqual, // <=
@@ -13,7 +14,7 @@ pub enum VersionRelation {
 
 impl fmt::Display for VersionRelation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        return match self {
+        match self {
             VersionRelation::StrictlyLess =>    write!(f, "<<"),
             VersionRelation::LessOrEqual =>     write!(f, "<="),
             VersionRelation::Equal =>           write!(f, "="),
@@ -39,13 +40,29 @@ impl FromStr for VersionRelation {
 }
 
 
-#[derive(PartialEq,Eq)]
+#[derive(Clone,PartialEq,Eq)]
 pub struct DebianVersionNum {
     epoch : String,
     upstream : String,
     debian : String
 }
 
+impl DebianVersionNum {
+    /// The epoch, or "" if there is none (which compares like 0).
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    /// The Debian revision, or "" for a native package.
+    pub fn debian_revision(&self) -> &str {
+        &self.debian
+    }
+}
+
 impl fmt::Display for DebianVersionNum {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let epoch_sep = if self.epoch.is_empty() {""} else {":"};
@@ -74,23 +91,23 @@ impl FromStr for DebianVersionNum {
     }
 }
 
-impl Ord for DebianVersionNum {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+impl PartialOrd for DebianVersionNum {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl PartialOrd for DebianVersionNum {
+impl Ord for DebianVersionNum {
     // https://www.debian.org/doc/debian-policy/ch-controlfields.html#version
     // wow this is painful
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         fn extract_nonnum(s: &str) -> (&str,&str) {
             let first_digit = s.find(|c:char| c.is_ascii_digit());
             match first_digit {
                 None => (s, ""),
                 Some(fd) => {
                     let (nonnum, rest) = s.split_at(fd);
-                    return (nonnum, rest);
+                    (nonnum, rest)
                 }
             }
         }
@@ -100,7 +117,7 @@ impl PartialOrd for DebianVersionNum {
                 None => (s, ""),
                 Some(fd) => {
                     let (num, rest) = s.split_at(fd);
-                    return (num, rest);
+                    (num, rest)
                 }
             }
         }
@@ -156,8 +173,8 @@ impl PartialOrd for DebianVersionNum {
                 let (other_num, other_rest1) = extract_num(other_rest);
 
                 let (sn_i, on_i) = (
-                    match self_num.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-                    match other_num.parse::<i32>() { Err(_) => 0, Ok(e) => e });
+                    self_num.parse::<i32>().unwrap_or(0),
+                    other_num.parse::<i32>().unwrap_or(0));
 
                 if sn_i != on_i {
                     return sn_i.partial_cmp(&on_i).unwrap();
@@ -167,21 +184,21 @@ impl PartialOrd for DebianVersionNum {
         }
 
         let (epoch, other_epoch) = (
-            match self.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e },
-            match other.epoch.parse::<i32>() { Err(_) => 0, Ok(e) => e });
+            self.epoch.parse::<i32>().unwrap_or(0),
+            other.epoch.parse::<i32>().unwrap_or(0));
         if epoch != other_epoch {
-            return Some(epoch.partial_cmp(&other_epoch).unwrap());
+            return epoch.cmp(&other_epoch);
         }
         let ups = debian_cmp(&self.upstream, &other.upstream);
         if ups != Equal {
-            return Some(ups);
+            return ups;
         }
-        return Some(debian_cmp(&self.debian, &other.debian));
+        debian_cmp(&self.debian, &other.debian)
     }
 }
 
 pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, second: &DebianVersionNum) -> bool {
-    return match op {
+    match op {
         VersionRelation::StrictlyLess => first < second,
         VersionRelation::LessOrEqual => first <= second,
         VersionRelation::Equal => first == second,
@@ -189,3 +206,29 @@ pub fn cmp_debversion_with_op(op:&VersionRelation, first: &DebianVersionNum, sec
         VersionRelation::StrictlyGreater => first > second
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> DebianVersionNum {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn longer_parts_sort_later() {
+        assert!(v("1.0a") > v("1.0"));
+        assert!(v("1.0") < v("1.0+b1"));
+        assert!(v("1.0") < v("1.0.1"));
+    }
+
+    #[test]
+    fn numbers_epochs_and_revisions() {
+        assert!(v("1.2-9") < v("1.2-10"));
+        assert!(v("1:0.4.5+cvs20030824-9") < v("1:0.4.5+cvs20030824-10"));
+        assert!(v("1:1.0") > v("2.0"));
+        assert!(v("2-a") > v("1-b"));
+        assert!(v("1-a") < v("1-b"));
+        assert_eq!(v("1.0-1").cmp(&v("1.0-1")), Equal);
+    }
+}
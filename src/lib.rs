@@ -1 +1,19 @@
+//! A small Debian package manager: Packages is a database of available and installed packages, loaded from
+//! Packages indices and dpkg status files, with queries and solvers over it and a verifier that checks MD5sums
+//! against a server. The rpkg binary is a REPL over this library.
+//!
+//! ```no_run
+//! use rpkg::packages::AlternativePreference;
+//! use rpkg::Packages;
+//!
+//! let mut packages = Packages::new();
+//! packages.parse_packages("Packages").unwrap();
+//! packages.parse_installed("/var/lib/dpkg/status").unwrap();
+//! let plan = packages.how_to_install("3depict", AlternativePreference::HighestVersion).unwrap();
+//! println!("{} more packages to install", plan.to_install.len());
+//! ```
+
 pub mod debversion;
+pub mod packages;
+
+pub use crate::packages::Packages;
@@ -1,143 +1,57 @@
+use std::io::{BufRead, IsTerminal};
+
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use rpkg::debversion;
-use crate::packages::Packages;
-
-mod packages;
-
-fn check_syntax(n: usize, cmd_fragments:&Vec<&str>, arg: &str) -> bool {
-    let cmd : &str = &cmd_fragments.get(0).unwrap();
-    if cmd_fragments.len() != n {
-        println!("syntax: {} {}", cmd, arg);
-        return false
-    }
-    return true
-}
+use crate::commands::{process_command, split_commands, CommandStatus, State};
+use crate::completion::RpkgHelper;
+use crate::output::OutputFormat;
 
-fn process_command(state: &mut Packages, cmdline: &str) -> bool {
-    let cmd_fragments: Vec<&str> = cmdline.split(" ").collect();
-    if cmdline.is_empty() { return false }
-    let cmd : &str = &cmd_fragments.get(0).unwrap();
-    match cmd {
-        "quit" => { 
-            return true 
-        },
-        "load-csv" | "lc" => {
-            if !check_syntax(2, &cmd_fragments, "<csvfile-name>") { return false; }
-            let arg = cmd_fragments.get(1).unwrap();
-            state.parse_csv(arg)
-        }
-        // parsers.rs
-        "load-packages" | "lp" => {
-            if !check_syntax(2, &cmd_fragments, "<pkgfile-name>") { return false; }
-            let arg = cmd_fragments.get(1).unwrap();
-            state.parse_packages(arg)
-        }
-        "load-installed" | "li" => {
-            if !check_syntax(2, &cmd_fragments, "<pkgfile-name>") { return false; }
-            let arg = cmd_fragments.get(1).unwrap();
-            state.parse_installed(arg)
-        }
-        // convenience function, also depends on parsers.rs
-        "load-defaults" | "ld" => {
-            state.parse_packages("data/mirror.csclub.uwaterloo.ca_debian_dists_sid_main_binary-amd64_Packages");
-            state.parse_installed("data/installed-packages")
-        }
+mod commands;
+mod completion;
+mod output;
 
-        "info" => {
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
-            let pkg = cmd_fragments.get(1).unwrap();
-            state.print_info(pkg)
-        }
-        "deps" => {
-            // test: deps 0ad
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
-            let pkg = cmd_fragments.get(1).unwrap();
-            state.print_deps(pkg)
-        }
-
-        // deps-available.rs
-        "deps-available" => {
-            // test: deps-available 3depict
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
-            let pkg = cmd_fragments.get(1).unwrap();
-            state.deps_available(pkg)
-        }
-
-        // solvers.rs, and deps-available.rs for how-to-install
-        "transitive-dep-solution" => {
-            // test: transitive-dep-solution 0ad
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
-            let pkg = cmd_fragments.get(1).unwrap();
-            state.print_transitive_dep_solution(pkg)
-        }
-        "how-to-install" => {
-            if !check_syntax(2, &cmd_fragments, "<pkg>") { return false; }
-            let pkg = cmd_fragments.get(1).unwrap();
-            state.print_how_to_install(pkg)
-        }
-
-        "set-server" => {
-            if !check_syntax(2, &cmd_fragments, "<server>") { return false; }
-            let server = cmd_fragments.get(1).unwrap();
-            state.set_server(server)
-        }
-        "execute" => {
-            state.execute();
-        }
-        "enq-verify" => {
-            let cmd : &str = &cmd_fragments.get(0).unwrap();
-            if cmd_fragments.len() < 2 || cmd_fragments.len() > 3 {
-                println!("syntax: {} <pkg> [<version>]", cmd);
-                return false
+/// Runs commands non-interactively, stopping at the first failed command unless keep_going is set.
+/// A quit command stops the run after executing any enqueued verification requests and waiting for background jobs.
+/// A checksum server started with --keep-serving keeps serving after the last command, until the process is killed.
+/// Returns true if all the commands succeeded.
+fn run_batch<I: Iterator<Item = String>>(state: &mut State, commands: I, keep_going: bool) -> bool {
+    let mut all_succeeded = true;
+    for cmdline in commands {
+        let cmdline = cmdline.trim();
+        if cmdline.is_empty() || cmdline.starts_with('#') {
+            continue;
+        }
+        match process_command(state, cmdline) {
+            CommandStatus::Succeeded => (),
+            CommandStatus::Failed => {
+                all_succeeded = false;
+                if !keep_going { return false; }
             }
-            let pkg = cmd_fragments.get(1).unwrap();
-            if cmd_fragments.len() == 2 {
-                state.enq_verify(pkg);
-            } else {
-                let version = cmd_fragments.get(2).unwrap();
-                state.enq_verify_with_version(pkg, version);
+            CommandStatus::Quit => {
+                let executed = state.execute() == CommandStatus::Succeeded;
+                return state.wait(None) == CommandStatus::Succeeded && executed && all_succeeded;
             }
         }
-
-        "output-md5s" => {
-            if !check_syntax(2, &cmd_fragments, "<output-file>") { return false; }
-            let fname = cmd_fragments.get(1).unwrap();
-            state.output_md5s(fname);
-        }
-        "test-version-compare" => {
-            if !check_syntax(3, &cmd_fragments, "<version1> <version2>") { return false; }
-            let v1 = cmd_fragments.get(1).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
-            let v2 = cmd_fragments.get(2).unwrap().parse::<debversion::DebianVersionNum>().unwrap();
-            println!("{} and {}: {:?}", v1, v2, v1.cmp(&v2));
-            // 1:0.4.5+cvs20030824-9 vs 1:0.4.5+cvs20030824-10
-            // a vs b
-            // a vs a
-            // b vs a
-            // 1-a vs 1-b
-            // 2-a vs 1-b
-            // a vs ~a
-        }
-        _ => {
-            println!("couldn't understand cmd {:?}", cmd)
-        }
     }
-    return false;
+    // requests still enqueued or sent in the background at the end of the input are executed too, but don't affect the exit status
+    state.execute();
+    state.wait(None);
+    state.wait_for_checksum_server();
+    all_succeeded
 }
 
-fn main() {
-    let mut state : Packages = Packages::new();
-
-    // bonus (0 points): implement command completion!
-    let mut rl = Editor::<()>::new();
-    if rl.load_history("history.txt").is_err() {}
+fn run_repl(state: &mut State) {
+    let mut rl = Editor::<RpkgHelper>::new();
+    rl.set_helper(Some(RpkgHelper::new()));
+    let _ = rl.load_history("history.txt");
     loop {
         let readline = rl.readline("$ ");
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                if process_command(&mut state, &line) { break }
+                if process_command(state, &line) == CommandStatus::Quit { break }
+                rl.helper_mut().unwrap().update_package_names(&state.packages);
             },
             Err(ReadlineError::Interrupted) => {
                 break
@@ -152,4 +66,52 @@ fn main() {
         }
     }
     rl.save_history("history.txt").unwrap();
+    state.execute();
+    state.wait(None);
+}
+
+fn usage() {
+    println!("usage: rpkg [--keep-going] [--format text|json] [-c \"<cmd>; <cmd>...\" | --script <file>]");
+    println!("Without -c or --script, commands are read from a REPL, or from stdin if it is not a terminal.");
+}
+
+fn main() {
+    let mut keep_going = false;
+    let mut output_format = OutputFormat::Text;
+    let mut commands : Option<Vec<String>> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), commands.is_some()) {
+            ("--keep-going", _) => keep_going = true,
+            ("--format", _) => match args.next().map(|f| f.parse::<OutputFormat>()) {
+                Some(Ok(f)) => output_format = f,
+                Some(Err(e)) => { println!("{}", e); std::process::exit(2) }
+                None => { usage(); std::process::exit(2) }
+            },
+            ("-c", false) => match args.next() {
+                None => { usage(); std::process::exit(2) }
+                Some(cmds) => commands = Some(split_commands(&cmds))
+            },
+            ("--script", false) => match args.next().map(std::fs::read_to_string) {
+                Some(Ok(script)) => commands = Some(script.lines().map(String::from).collect()),
+                Some(Err(e)) => { println!("couldn't read script: {}", e); std::process::exit(2) }
+                None => { usage(); std::process::exit(2) }
+            },
+            _ => { usage(); std::process::exit(2) }
+        }
+    }
+
+    let succeeded = {
+        // enqueued requests are executed at the end of the run, before the process exits
+        let mut state = State::new(output_format);
+        match commands {
+            Some(commands) => run_batch(&mut state, commands.into_iter(), keep_going),
+            None if !std::io::stdin().is_terminal() => {
+                let lines = std::io::stdin().lock().lines().map_while(Result::ok);
+                run_batch(&mut state, lines, keep_going)
+            }
+            None => { run_repl(&mut state); true }
+        }
+    };
+    std::process::exit(if succeeded { 0 } else { 1 });
 }
@@ -0,0 +1,602 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use rpkg::packages::VerifyPolicy;
+use rpkg::packages::checksums::ChecksumType;
+use rpkg::packages::metadata::PackageMetadata;
+use rpkg::packages::results::{AlternativeProblem, CacheClearResult, CacheInfoResult, CheckResult, ChecksumServerStats, DebMatch,
+                              DebOutcome, DebPoolReport, DebVerification, DepsAvailableResult, DepsResult, DiffPackagesResult,
+                              HowToInstallResult, InfoResult, InstallabilityResult, JobStatus, JobsResult, QueuedVerification,
+                              SearchResult, ServersResult, TransitiveDepSolutionResult, UninstallableReason, VerificationOutcome,
+                              VerificationReport, VerificationResult, VerificationSummary, VerifyCacheClearResult, VerifyCacheStats,
+                              VersionChangeKind};
+
+/// How command results are printed: the traditional human-readable text, or one JSON document per result.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format {:?}, expected text or json", s))
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json")
+        }
+    }
+}
+
+/// A command result that can be printed either as text or as JSON.
+pub trait CommandResult: Serialize {
+    fn print_text(&self);
+
+    /// Whether the result means the command succeeded; e.g. a check that found problems didn't.
+    fn succeeded(&self) -> bool {
+        true
+    }
+
+    /// Prints the result in output_format; JSON results are printed as one document per line.
+    fn print(&self, output_format: OutputFormat) {
+        match output_format {
+            OutputFormat::Text => self.print_text(),
+            OutputFormat::Json => println!("{}", serde_json::to_string(self).unwrap())
+        }
+    }
+}
+
+/// How many packages a file had, e.g. as loaded or saved.
+#[derive(Serialize)]
+pub struct PackageCount {
+    /// available, installed or saved
+    pub what: String,
+    pub packages: usize,
+}
+
+impl CommandResult for PackageCount {
+    fn print_text(&self) {
+        println!("Packages {}: {}", self.what, self.packages);
+    }
+}
+
+#[derive(Serialize)]
+pub struct ResultsSaved {
+    pub file: String,
+    pub saved: usize,
+}
+
+impl CommandResult for ResultsSaved {
+    fn print_text(&self) {
+        println!("Results saved: {}", self.saved);
+    }
+}
+
+/// How two Debian version numbers compare.
+#[derive(Serialize)]
+pub struct VersionComparison {
+    pub version1: String,
+    pub version2: String,
+    pub ordering: VersionOrdering,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionOrdering {
+    Less,
+    Equal,
+    Greater,
+}
+
+impl From<Ordering> for VersionOrdering {
+    fn from(ordering: Ordering) -> Self {
+        match ordering {
+            Ordering::Less => VersionOrdering::Less,
+            Ordering::Equal => VersionOrdering::Equal,
+            Ordering::Greater => VersionOrdering::Greater
+        }
+    }
+}
+
+impl CommandResult for VersionComparison {
+    fn print_text(&self) {
+        println!("{} and {}: {:?}", self.version1, self.version2, self.ordering);
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorResult {
+    pub error: String,
+}
+
+impl CommandResult for ErrorResult {
+    fn print_text(&self) {
+        println!("{}", self.error);
+    }
+
+    fn succeeded(&self) -> bool {
+        false
+    }
+}
+
+impl CommandResult for InfoResult {
+    fn print_text(&self) {
+        println!("Package: {}", self.package);
+        if let Some(version) = &self.version {
+            println!("Version: {}", version);
+            println!("MD5Sum: {}", self.md5sum.as_deref().unwrap_or(""));
+            if let Some(sha256) = &self.sha256 {
+                println!("SHA256: {}", sha256);
+            }
+            if let Some(sha512) = &self.sha512 {
+                println!("SHA512: {}", sha512);
+            }
+            println!("Depends: {}", self.depends.iter().flatten().format(", "));
+        }
+        if let Some(metadata) = &self.metadata {
+            print_metadata(metadata);
+        }
+        if let Some(installed_version) = &self.installed_version {
+            println!("Installed-Version: {}", installed_version);
+        }
+        if let Some(newer_available) = self.newer_available {
+            println!("Newer-Available: {:?}", newer_available);
+        }
+    }
+}
+
+fn print_metadata(metadata: &PackageMetadata) {
+    fn print_field(key: &str, value: &str) {
+        // continuation lines are indented by a space, as in the Packages file
+        let value = value.replace('\n', "\n ");
+        if value.is_empty() || value.starts_with('\n') {
+            println!("{}:{}", key, value);
+        } else {
+            println!("{}: {}", key, value);
+        }
+    }
+    let fields = [
+        ("Priority", metadata.priority.map(|p| p.to_string())),
+        ("Section", metadata.section.clone()),
+        ("Maintainer", metadata.maintainer.clone()),
+        ("Installed-Size", metadata.installed_size.map(|s| s.to_string())),
+        ("Size", metadata.size.map(|s| s.to_string())),
+        ("Homepage", metadata.homepage.clone()),
+        ("Filename", metadata.filename.clone()),
+    ];
+    for (key, value) in fields.iter() {
+        if let Some(value) = value {
+            print_field(key, value);
+        }
+    }
+    for (key, value) in &metadata.other_fields {
+        print_field(key, value);
+    }
+    if let Some(description) = &metadata.description {
+        match &metadata.long_description {
+            Some(long_description) => print_field("Description", &format!("{}\n{}", description, long_description)),
+            None => print_field("Description", description)
+        }
+    }
+}
+
+impl CommandResult for DepsResult {
+    fn print_text(&self) {
+        println!("{:?} depends on {:?}", self.package, self.depends.iter().format(", ").to_string());
+    }
+}
+
+impl CommandResult for DepsAvailableResult {
+    fn print_text(&self) {
+        println!("Package {}:", self.package);
+        for dep in &self.dependencies {
+            println!("- dependency {:?}", dep.dependency);
+            match (&dep.satisfied_by, &dep.installed_version) {
+                (Some(package_name), Some(version)) => println!("+ {} satisfied by installed version {}", package_name, version),
+                _ => println!("-> not satisfied")
+            }
+        }
+    }
+}
+
+impl CommandResult for CheckResult {
+    fn print_text(&self) {
+        for target in &self.unmet {
+            println!("Unmet dependency {:?}:", target.target);
+            for p in &target.packages {
+                println!("- {} {}: depends on {:?}", p.package, p.version, p.clause);
+            }
+        }
+        for target in &self.conflicting {
+            println!("Conflicting package {:?}:", target.target);
+            for p in &target.packages {
+                println!("- {} {}: conflicts with {:?}, installed version {}", p.package, p.version, p.clause,
+                         p.conflicting_version.as_deref().unwrap_or(""));
+            }
+        }
+        println!("{} broken installed packages, {} problems", self.broken_packages, self.problems);
+    }
+
+    fn succeeded(&self) -> bool {
+        self.broken_packages == 0
+    }
+}
+
+impl CommandResult for InstallabilityResult {
+    fn print_text(&self) {
+        for p in &self.uninstallable {
+            println!("{:?} {} is not installable:", p.package, p.version);
+            print_uninstallable_reason(&p.reason, 1);
+        }
+        println!("{} of {} packages are not installable", self.uninstallable.len(), self.checked);
+    }
+
+    fn succeeded(&self) -> bool {
+        self.uninstallable.is_empty()
+    }
+}
+
+fn print_uninstallable_reason(reason: &UninstallableReason, depth: usize) {
+    let indent = "  ".repeat(depth);
+    println!("{}- dependency {:?}", indent, reason.dependency);
+    for problem in &reason.problems {
+        match problem {
+            AlternativeProblem::Missing { package } => println!("{}  {} is not available", indent, package),
+            AlternativeProblem::NoProvider { package } => println!("{}  {} is a virtual package with no suitable provider", indent, package),
+            AlternativeProblem::WrongVersion { package, version, required } =>
+                println!("{}  {} has version {}, which does not satisfy {}", indent, package, version, required),
+            AlternativeProblem::Uninstallable { package, version, reason } => {
+                println!("{}  {} {} is not installable:", indent, package, version);
+                if let Some(reason) = reason {
+                    print_uninstallable_reason(reason, depth + 2);
+                }
+            }
+        }
+    }
+}
+
+impl CommandResult for TransitiveDepSolutionResult {
+    fn print_text(&self) {
+        println!("{:?} transitive dependency solution: {:?}", self.package, self.solution.iter().format(", ").to_string());
+    }
+}
+
+impl CommandResult for HowToInstallResult {
+    fn print_text(&self) {
+        println!("Package {}:", self.package);
+        println!("{:?} to install: {:?}", self.package, self.to_install.iter().format(", ").to_string());
+        // e.g. loaded from a CSV file, which has no sizes
+        if self.unknown_sizes == self.plan.len() {
+            return;
+        }
+        for p in &self.plan {
+            let upgrade = p.installed_version.as_ref().map(|v| format!(" (upgrading from {})", v)).unwrap_or_default();
+            println!("  {} {}{}: download {}, disk {}", p.package, p.version.as_deref().unwrap_or("-"), upgrade,
+                     p.download_size.map_or(String::from("unknown"), format_size),
+                     p.installed_size_delta.map_or(String::from("unknown"), format_size_delta));
+        }
+        println!("Total download: {}, disk space change: {}", format_size(self.total_download_size), format_size_delta(self.total_installed_size_delta));
+        if self.unknown_sizes > 0 {
+            println!("({} packages of unknown size are not counted)", self.unknown_sizes);
+        }
+    }
+}
+
+/// Formats a number of bytes like apt does, e.g. 56.1 kB.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1000 => format!("{} B", b),
+        b if b < 1_000_000 => format!("{:.1} kB", b as f64 / 1e3),
+        b if b < 1_000_000_000 => format!("{:.1} MB", b as f64 / 1e6),
+        b => format!("{:.1} GB", b as f64 / 1e9)
+    }
+}
+
+fn format_size_delta(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_size(bytes.unsigned_abs()))
+}
+
+impl CommandResult for SearchResult {
+    fn print_text(&self) {
+        for m in &self.results {
+            let version = m.version.as_deref().unwrap_or("-");
+            match &m.installed_version {
+                Some(installed_version) => println!("{} {} [installed: {}]", m.package, version, installed_version),
+                None => println!("{} {}", m.package, version)
+            }
+        }
+        println!("{} of {} matching packages shown", self.results.len(), self.total);
+    }
+}
+
+impl CommandResult for DiffPackagesResult {
+    fn print_text(&self) {
+        println!("--- {}", self.old);
+        println!("+++ {}", self.new);
+        for p in &self.added {
+            println!("added {} {}", p.package, p.version);
+        }
+        for p in &self.removed {
+            println!("removed {} {}", p.package, p.version);
+        }
+        for c in &self.version_changes {
+            let downgrade = if c.downgrade { ", downgrade" } else { "" };
+            println!("changed {} {} -> {} ({}{})", c.package, c.old_version, c.new_version, c.kind, downgrade);
+        }
+        for c in &self.dependency_changes {
+            println!("dependencies of {}:", c.package);
+            for dep in &c.removed {
+                println!("  - {}", dep);
+            }
+            for dep in &c.added {
+                println!("  + {}", dep);
+            }
+        }
+        let count = |kind| self.version_changes.iter().filter(|c| c.kind == kind).count();
+        println!("{} added, {} removed, {} version changes ({} epoch, {} upstream, {} debian revision), {} with dependency changes",
+                 self.added.len(), self.removed.len(), self.version_changes.len(), count(VersionChangeKind::Epoch),
+                 count(VersionChangeKind::Upstream), count(VersionChangeKind::DebianRevision), self.dependency_changes.len());
+    }
+}
+
+impl CommandResult for QueuedVerification {
+    fn print_text(&self) {
+        if self.cached {
+            println!("using cached result for {}", self.url);
+        } else {
+            println!("queueing request {}", self.url);
+        }
+    }
+}
+
+impl CommandResult for VerificationReport {
+    fn print_text(&self) {
+        for result in &self.results {
+            print_verification_result(result);
+        }
+    }
+
+    fn succeeded(&self) -> bool {
+        self.results.iter().all(VerificationResult::is_clean)
+    }
+}
+
+fn print_verification_result(result: &VerificationResult) {
+    let mut attempts = if result.attempts > 1 { format!(" (after {} attempts)", result.attempts) } else { String::new() };
+    if result.algo != ChecksumType::Md5 {
+        attempts.push_str(&format!(" ({})", result.algo));
+    }
+    if result.cached {
+        attempts.push_str(" (cached)");
+    }
+    if let (Some(server), Some(hash)) = (&result.conflicting_server, &result.conflicting_hash) {
+        attempts.push_str(&format!(" (suspicious: {} returned {}, {} returned {})", result.server,
+                                   result.remote_hash.as_deref().unwrap_or(""), server, hash));
+    }
+    match result.outcome {
+        VerificationOutcome::Match | VerificationOutcome::Mismatch =>
+            println!("verifying {}, matches: {:?}{}", result.package, result.outcome == VerificationOutcome::Match, attempts),
+        VerificationOutcome::HttpError =>
+            println!("got error {} on request for package {} version {}{}", result.http_status, result.package, result.version, attempts),
+        VerificationOutcome::TransportError =>
+            println!("request for package {} version {} failed: {}{}", result.package, result.version,
+                     result.error.as_deref().unwrap_or("no response"), attempts),
+        VerificationOutcome::Cancelled =>
+            println!("request for package {} version {} cancelled{}", result.package, result.version, attempts)
+    }
+}
+
+// only the requests that didn't match (and weren't cancelled) are listed
+impl CommandResult for VerificationSummary {
+    fn print_text(&self) {
+        // cancelled requests are only counted
+        for result in self.results.iter().filter(|r| !r.is_clean() && r.outcome != VerificationOutcome::Cancelled) {
+            print_verification_result(result);
+        }
+        println!("{} verified in {:.1}s: {} match, {} mismatch, {} errors ({} HTTP, {} transport){}{}{}",
+                 self.results.len() - self.cancelled, self.elapsed_ms / 1000.0, self.matches, self.mismatches,
+                 self.http_errors + self.transport_errors, self.http_errors, self.transport_errors,
+                 if self.cancelled > 0 { format!(", {} cancelled", self.cancelled) } else { String::new() },
+                 if self.suspicious > 0 { format!(", {} suspicious", self.suspicious) } else { String::new() },
+                 if self.cached > 0 { format!(", {} cached", self.cached) } else { String::new() });
+        if !self.skipped.is_empty() {
+            println!("{} skipped, with no known checksum", self.skipped.len());
+        }
+    }
+
+    fn succeeded(&self) -> bool {
+        self.results.iter().all(VerificationResult::is_clean)
+    }
+}
+
+impl CommandResult for ServersResult {
+    fn print_text(&self) {
+        for s in &self.servers {
+            let auth = s.auth.as_ref().map_or(String::new(), |auth| format!(" ({})", auth));
+            if s.requests == 0 {
+                println!("{}{} {}: no requests", s.server, auth, if s.up { "up" } else { "down" });
+                continue;
+            }
+            println!("{}{} {}: {} requests, {:.1}% succeeded, {}{}", s.server, auth, if s.up { "up" } else { "down" }, s.requests,
+                     100.0 * s.successes as f64 / s.requests as f64,
+                     s.mean_latency_ms.map_or(String::from("no latency"), |l| format!("mean latency {:.1} ms", l)),
+                     if s.consecutive_failures > 0 { format!(", {} failures in a row", s.consecutive_failures) } else { String::new() });
+        }
+    }
+}
+
+impl CommandResult for VerifyPolicy {
+    fn print_text(&self) {
+        println!("connect timeout {:?}, timeout {:?}, {} retries with backoff {:?} up to {:?}",
+                 self.connect_timeout, self.timeout, self.max_retries, self.backoff, self.max_backoff);
+        println!("at most {} requests in flight, {}, keep-alive {}", self.max_in_flight,
+                 self.max_rate.map_or(String::from("no rate limit"), |r| format!("at most {} requests/s", r)),
+                 if self.keep_alive { "on" } else { "off" });
+        if self.cache_ttl.is_zero() {
+            println!("results not cached");
+        } else {
+            println!("results cached for {:?}", self.cache_ttl);
+        }
+        let proxy = match self.proxy.as_deref() {
+            None => String::from("proxy from the environment"),
+            Some("") => String::from("no proxy"),
+            Some(proxy) => format!("proxy {}", proxy)
+        };
+        let no_proxy = match self.no_proxy.as_deref() {
+            None => String::new(),
+            Some(no_proxy) => format!(" except for {:?}", no_proxy)
+        };
+        println!("{}{}, {}", proxy, no_proxy, self.ca_bundle.as_ref().map_or(String::from("system CA certificates"), |c| format!("CA bundle {}", c)));
+    }
+}
+
+impl CommandResult for JobStatus {
+    fn print_text(&self) {
+        println!("[{}] executing {} requests in the background", self.id, self.total);
+    }
+}
+
+impl CommandResult for JobsResult {
+    fn print_text(&self) {
+        if self.jobs.is_empty() {
+            println!("no background jobs");
+        }
+        for job in &self.jobs {
+            println!("[{}] {}: {}/{} verified, {} mismatches, {} errors, {:.1}s", job.id, job.state, job.done, job.total,
+                     job.mismatches, job.errors, job.elapsed_ms / 1000.0);
+        }
+    }
+}
+
+impl CommandResult for VerifyCacheStats {
+    fn print_text(&self) {
+        match (&self.path, self.size) {
+            (None, _) => println!("verification cache: in memory only"),
+            (Some(path), None) => println!("verification cache: {} (not written yet)", path),
+            (Some(path), Some(size)) => println!("verification cache: {} ({} bytes)", path, size),
+        }
+        println!("{} hashes, {} fresh (TTL {:?}); {} hits and {} misses this session",
+                 self.entries, self.fresh, std::time::Duration::from_secs(self.ttl_secs), self.hits, self.misses);
+    }
+}
+
+impl CommandResult for VerifyCacheClearResult {
+    fn print_text(&self) {
+        println!("removed {} cached hashes", self.removed);
+    }
+}
+
+#[derive(Serialize)]
+pub struct ServingChecksums {
+    pub addr: String,
+    pub checksums: usize,
+}
+
+impl CommandResult for ServingChecksums {
+    fn print_text(&self) {
+        println!("serving {} checksums on {}", self.checksums, self.addr);
+    }
+}
+
+impl CommandResult for ChecksumServerStats {
+    fn print_text(&self) {
+        println!("{}: {} requests, {} served, {} wrong hashes, {} injected errors, {} not found",
+                 self.addr, self.requests, self.served, self.wrong_hashes, self.injected_errors, self.not_found);
+    }
+}
+
+impl CommandResult for CacheInfoResult {
+    fn print_text(&self) {
+        let cache_dir = match &self.cache_dir {
+            None => { println!("caching is off"); return; }
+            Some(cache_dir) => cache_dir
+        };
+        println!("cache directory {} (format version {})", cache_dir, self.format_version);
+        for entry in &self.entries {
+            match (&entry.kind, &entry.source) {
+                (Some(kind), Some(source)) => println!("- {}: {} of {}, {} bytes, {}", entry.path, kind, source, entry.size,
+                                                        if entry.valid { "valid" } else { "stale" }),
+                _ => println!("- {}: {} bytes, unreadable or from another version", entry.path, entry.size)
+            }
+        }
+        println!("{} cache files", self.entries.len());
+    }
+}
+
+impl CommandResult for CacheClearResult {
+    fn print_text(&self) {
+        println!("removed {} cache files", self.removed);
+    }
+}
+
+impl CommandResult for DebVerification {
+    fn print_text(&self) {
+        let path = self.path.as_deref().unwrap_or("");
+        let package = match (&self.package, &self.version) {
+            (Some(package), Some(version)) => format!("{} {}", package, version),
+            (Some(package), None) => package.clone(),
+            _ => String::new()
+        };
+        match self.outcome {
+            DebOutcome::Ok => {
+                let matched_by = if self.matched_by == Some(DebMatch::Control) { "control data" } else { "file name" };
+                let checked = std::iter::once(String::from("size")).chain(self.checked.iter().map(|c| c.to_string()));
+                println!("ok: {} is {} (by {}; {} match)", path, package, matched_by, checked.format(", "));
+            }
+            DebOutcome::Corrupt => {
+                let mut problems = vec![];
+                if let Some(error) = &self.error {
+                    problems.push(error.clone());
+                }
+                if let (Some(size), Some(expected_size)) = (self.size, self.expected_size) {
+                    if size != expected_size {
+                        problems.push(format!("size {}, expected {}", size, expected_size));
+                    }
+                }
+                problems.extend(self.mismatched.iter().map(|c| format!("{} differs", c)));
+                println!("corrupt: {} ({}): {}", path, package, problems.iter().format("; "));
+            }
+            DebOutcome::Unknown => match (&self.error, self.package.is_some()) {
+                (Some(error), _) => println!("unknown: {}: {}", path, error),
+                (None, true) => println!("unknown: {}: {} isn't in the index", path, package),
+                (None, false) => println!("unknown: {}", path)
+            },
+            DebOutcome::Missing => println!("missing: {} ({})", self.filename.as_deref().unwrap_or(""), package),
+        }
+    }
+
+    fn succeeded(&self) -> bool {
+        self.outcome == DebOutcome::Ok
+    }
+}
+
+// only the files that aren't ok are listed
+impl CommandResult for DebPoolReport {
+    fn print_text(&self) {
+        for result in self.results.iter().filter(|r| r.outcome != DebOutcome::Ok) {
+            result.print_text();
+        }
+        println!("{} files ({:.1} MB) in {}, {:.1}s: {} ok, {} corrupt, {} unknown; {} missing",
+                 self.files, self.bytes as f64 / 1e6, self.dir, self.elapsed_ms / 1000.0, self.ok, self.corrupt, self.unknown, self.missing);
+    }
+
+    fn succeeded(&self) -> bool {
+        self.ok == self.files && self.missing == 0
+    }
+}
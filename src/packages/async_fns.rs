@@ -1,118 +1,866 @@
-use urlencoding::encode;
 
-use curl::easy::{Easy2, Handler, WriteError};
+use curl::easy::{Auth, Easy2, Handler, List, WriteError};
 use curl::multi::{Easy2Handle, Multi};
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::str;
-use std::sync::atomic::{AtomicI32, Ordering};
+
+use regex::Regex;
+use serde::Serialize;
 
 use crate::Packages;
+use crate::packages::checksums::ChecksumType;
+use crate::packages::mirrors::Mirrors;
+use crate::packages::verify_cache::VerifyCache;
+use crate::packages::results::{JobState, JobStatus, JobsResult, QueuedVerification, ServersResult, VerificationOutcome, VerificationResult, VerificationSummary};
+
+#[derive(Default)]
+struct Collector {
+    // decoded once the transfer is done, since a chunk may end in the middle of a character
+    body : Vec<u8>,
+    /// from the Retry-After header of the response, if it gave a number of seconds
+    retry_after : Option<Duration>,
+}
 
-struct Collector(Box<String>);
 impl Handler for Collector {
     fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
-        (*self.0).push_str(str::from_utf8(&data.to_vec()).unwrap());
+        self.body.extend_from_slice(data);
         Ok(data.len())
     }
+
+    fn header(&mut self, data: &[u8]) -> bool {
+        if let Some((name, value)) = str::from_utf8(data).ok().and_then(|line| line.split_once(':')) {
+            if name.eq_ignore_ascii_case("retry-after") {
+                // an HTTP date is treated like a missing header, and the usual backoff applies
+                self.retry_after = value.trim().parse::<u64>().ok().map(Duration::from_secs);
+            }
+        }
+        true
+    }
 }
 
 const DEFAULT_SERVER : &str = "ece459.patricklam.ca:4590";
+// how often a running execute() checks whether it has been cancelled, and wait_jobs() reports progress
+const POLL_INTERVAL : Duration = Duration::from_millis(100);
+// enqueued requests are only sent on drop if the owner asked for it; otherwise they are dropped unsent
 impl Drop for Packages {
     fn drop(&mut self) {
-        self.execute()
+        if self.async_state.flush_on_drop {
+            self.execute();
+        }
+    }
+}
+
+/// How verification requests are sent: their timeouts, how failed requests are retried, and how hard the server is pushed.
+/// A request is retried if it got no response (the connection failed or timed out) or a 429 or 5xx response.
+/// Each retry goes to the next server; once every server has been tried, the n-th retry waits backoff * 2^(n-1),
+/// up to max_backoff, or as long as the server's Retry-After says (again up to max_backoff).
+/// At most max_in_flight requests are outstanding at a time, so at most that many connections are open, and
+/// requests (including retries) are started at most max_rate per second.
+/// The hashes received are cached for cache_ttl, and requests for which a fresh hash is cached aren't sent.
+/// Servers given as https://host:port are verified against ca_bundle, or the system's CA certificates if it's None.
+#[derive(Clone, Serialize)]
+pub struct VerifyPolicy {
+    #[serde(serialize_with = "serialize_seconds")]
+    pub connect_timeout : Duration,
+    /// for the whole transfer, including connecting
+    #[serde(serialize_with = "serialize_seconds")]
+    pub timeout : Duration,
+    /// how many times a request is retried after the first attempt
+    pub max_retries : u32,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub backoff : Duration,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub max_backoff : Duration,
+    pub max_in_flight : usize,
+    /// requests per second, or None for no limit
+    pub max_rate : Option<f64>,
+    /// whether connections are kept open (with TCP keep-alive) and reused by later requests of the same execute()
+    pub keep_alive : bool,
+    /// zero to turn the verification cache off
+    #[serde(serialize_with = "serialize_seconds")]
+    pub cache_ttl : Duration,
+    /// a PEM file of the CA certificates trusted for HTTPS
+    pub ca_bundle : Option<String>,
+    /// the proxy URL, or "" for none; if None, curl takes it from the http_proxy and https_proxy environment variables
+    pub proxy : Option<String>,
+    /// comma-separated hosts that are reached without the proxy; if None, curl takes them from no_proxy
+    pub no_proxy : Option<String>,
+}
+
+impl Default for VerifyPolicy {
+    fn default() -> Self {
+        VerifyPolicy {
+            connect_timeout : Duration::from_secs(10),
+            timeout : Duration::from_secs(30),
+            max_retries : 3,
+            backoff : Duration::from_millis(500),
+            max_backoff : Duration::from_secs(30),
+            max_in_flight : 16,
+            max_rate : None,
+            keep_alive : true,
+            cache_ttl : Duration::from_secs(24 * 60 * 60),
+            ca_bundle : None,
+            proxy : None,
+            no_proxy : None,
+        }
+    }
+}
+
+fn serialize_seconds<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl VerifyPolicy {
+    /// How long to wait before retrying a request that has been attempted attempts times.
+    fn retry_delay(&self, attempts: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = retry_after.unwrap_or_else(|| self.backoff.saturating_mul(1u32 << (attempts - 1).min(31)));
+        backoff.min(self.max_backoff)
+    }
+
+    /// The time between the starts of two requests allowed by max_rate.
+    fn start_interval(&self) -> Duration {
+        self.max_rate.map_or(Duration::ZERO, |rate| Duration::from_secs_f64(1.0 / rate))
+    }
+}
+
+/// The credentials sent to a checksum server: a user and password for HTTP basic auth, or a bearer token
+/// sent in an Authorization header.
+#[derive(Clone)]
+pub enum ServerAuth {
+    Basic { user : String, password : String },
+    Bearer { token : String },
+}
+
+impl ServerAuth {
+    // curl does the encoding of basic auth, and only sends it to the server, not e.g. to a proxy
+    fn apply<H>(&self, easy: &mut Easy2<H>) {
+        match self {
+            ServerAuth::Basic { user, password } => {
+                easy.username(user).unwrap();
+                easy.password(password).unwrap();
+                easy.http_auth(Auth::new().basic(true)).unwrap();
+            }
+            ServerAuth::Bearer { token } => {
+                let mut headers = List::new();
+                headers.append(&format!("Authorization: Bearer {}", token)).unwrap();
+                easy.http_headers(headers).unwrap();
+            }
+        }
+    }
+
+    /// What `servers` shows: the kind of credentials and the user, but no secrets.
+    fn describe(&self) -> String {
+        match self {
+            ServerAuth::Basic { user, .. } => format!("basic auth as {}", user),
+            ServerAuth::Bearer { .. } => String::from("bearer token"),
+        }
+    }
+}
+
+/// The URL of a server given as host:port (for plain HTTP), http://host:port or https://host:port, without a trailing /.
+fn base_url(server: &str) -> String {
+    let server = server.trim_end_matches('/');
+    if server.starts_with("http://") || server.starts_with("https://") {
+        String::from(server)
+    } else {
+        format!("http://{}", server)
     }
 }
 
-static EASYKEY_COUNTER: AtomicI32 = AtomicI32::new(0);
+/// How far execute() has got, as passed to the progress callback of execute_with_progress().
+#[derive(Clone, Default)]
+pub struct VerifyProgress {
+    /// requests with a final result (not counting retries)
+    pub done : usize,
+    pub total : usize,
+    pub mismatches : usize,
+    /// HTTP and transport errors
+    pub errors : usize,
+    pub elapsed : Duration,
+}
+
+impl VerifyProgress {
+    /// Finished requests per second so far.
+    pub fn rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { self.done as f64 / seconds } else { 0.0 }
+    }
+}
+
+/// What execute() needs to know about an enqueued request to send it and report on it.
+struct PendingRequest {
+    package : String,
+    version : String,
+    // the URL without the server and the query
+    path : String,
+    // the local checksums, strongest first; the strongest is asked for, then the next if the server doesn't have it
+    checksums : Vec<(ChecksumType, String)>,
+    // the server, type and hash, if the verification cache has a fresh one
+    cached : Option<(String, ChecksumType, String)>,
+}
+
+impl PendingRequest {
+    fn url(&self, server: &str, algo: ChecksumType) -> String {
+        match algo {
+            // servers that only know MD5sums don't know the parameter either
+            ChecksumType::Md5 => format!("{}{}", base_url(server), self.path),
+            _ => format!("{}{}?algo={}", base_url(server), self.path, algo)
+        }
+    }
+}
+
+/// A hash returned by a server.
+struct Answer {
+    server : usize,
+    hash : String,
+    latency : Duration,
+    // another server's different hash for the same package version, if one was seen
+    conflict : Option<(String, String)>,
+}
+
+/// A request while execute() is running: not started yet, waiting for its next attempt (easy), in the Multi (handle),
+/// or done (result). Tokens index the Vec of Transfers.
+struct Transfer {
+    pending : PendingRequest,
+    easy : Option<Easy2<Collector>>,
+    handle : Option<Easy2Handle<Collector>>,
+    attempts : u32,
+    // how many of the attempts asked for a weaker checksum after a 404; those aren't retries
+    fallbacks : u32,
+    // the server of the current or last attempt
+    server : usize,
+    // the index in pending.checksums of the checksum asked for
+    algo : usize,
+    not_before : Instant,
+    // a mismatching answer that another server is being asked about
+    second_opinion_of : Option<Answer>,
+    result : Option<VerificationResult>,
+}
+
+/// An execute() running on a thread of its own, from execute_in_background() until wait_jobs() collects its results.
+struct BackgroundJob {
+    id : usize,
+    started : Instant,
+    cancel : Arc<AtomicBool>,
+    progress : Arc<Mutex<VerifyProgress>>,
+    // None once joined
+    thread : Option<JoinHandle<Vec<VerificationResult>>>,
+}
+
+impl BackgroundJob {
+    fn status(&self) -> JobStatus {
+        let progress = self.progress.lock().unwrap();
+        let state = match &self.thread {
+            Some(thread) if !thread.is_finished() && self.cancel.load(Ordering::Relaxed) => JobState::Cancelling,
+            Some(thread) if !thread.is_finished() => JobState::Running,
+            _ => JobState::Finished
+        };
+        JobStatus {
+            id: self.id,
+            state,
+            done: progress.done,
+            total: progress.total,
+            mismatches: progress.mismatches,
+            errors: progress.errors,
+            elapsed_ms: self.started.elapsed().as_micros() as f64 / 1000.0,
+        }
+    }
+}
+
+// a job that nobody will wait for stops sending requests
+impl Drop for BackgroundJob {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
 
 pub struct AsyncState {
-    server : String,
-    easys: Vec<Easy2Handle<Collector>>,
-    multi: Multi,
-    easy_num_to_pkg_name: HashMap<i32, String>,
-    easy_num_to_version: HashMap<i32, String>,
-    easy_num_to_md5sum: HashMap<i32, String>
+    // shared with the background jobs, which keep the one they were started with if the servers change
+    pub(super) mirrors : Arc<Mutex<Mirrors>>,
+    pub(super) policy : VerifyPolicy,
+    flush_on_drop : bool,
+    // curl handles are only created when the requests are started, since there may be tens of thousands of these
+    pending: Vec<PendingRequest>,
+    pub(super) verify_cache: VerifyCache,
+    // keyed by server, as given to set_servers
+    auth: HashMap<String, ServerAuth>,
+    jobs: Vec<BackgroundJob>,
+    next_job: usize,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Default for AsyncState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AsyncState {
     pub fn new() -> AsyncState {
         AsyncState {
-            server : String::from(DEFAULT_SERVER),
-            easys: Vec::new(),
-            multi: Multi::new(),
-            easy_num_to_pkg_name: HashMap::new(),
-            easy_num_to_version: HashMap::new(),
-            easy_num_to_md5sum: HashMap::new()
+            mirrors : Arc::new(Mutex::new(Mirrors::new(DEFAULT_SERVER))),
+            policy : VerifyPolicy::default(),
+            flush_on_drop : false,
+            pending: Vec::new(),
+            verify_cache: VerifyCache::default(),
+            auth: HashMap::new(),
+            jobs: Vec::new(),
+            next_job: 1,
+            interrupt: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
 impl Packages {
     pub fn set_server(&mut self, new_server:&str) {
-        self.async_state.server = String::from(new_server);
+        self.set_servers(&[new_server]);
+    }
+
+    /// Sets the checksum servers (host:port, or http:// or https:// followed by host:port), in order of preference:
+    /// each request goes to the first one that isn't down, and is retried against the next ones.
+    /// The servers that were already there keep their health; background jobs already running keep the servers they
+    /// were started with. Panics if servers is empty.
+    pub fn set_servers(&mut self, servers: &[&str]) {
+        assert!(!servers.is_empty(), "no checksum servers");
+        let mut mirrors = self.async_state.mirrors.lock().unwrap().clone();
+        mirrors.set(servers);
+        self.async_state.mirrors = Arc::new(Mutex::new(mirrors));
+    }
+
+    /// Sets the credentials sent to server (as given to set_servers), or stops sending any if auth is None.
+    pub fn set_server_auth(&mut self, server: &str, auth: Option<ServerAuth>) {
+        match auth {
+            Some(auth) => { self.async_state.auth.insert(String::from(server), auth); }
+            None => { self.async_state.auth.remove(server); }
+        }
+    }
+
+    /// How each server has been answering since it was added.
+    pub fn servers(&self) -> ServersResult {
+        let mut servers = self.async_state.mirrors.lock().unwrap().status();
+        for server in &mut servers {
+            server.auth = self.async_state.auth.get(&server.server).map(ServerAuth::describe);
+        }
+        ServersResult { servers }
+    }
+
+    pub fn verify_policy(&self) -> &VerifyPolicy {
+        &self.async_state.policy
+    }
+
+    /// Sets the timeouts and retry policy of the requests sent by later calls to execute().
+    pub fn set_verify_policy(&mut self, policy: VerifyPolicy) {
+        self.async_state.policy = policy;
+    }
+
+    /// Whether dropping the database executes the requests still enqueued (discarding their results);
+    /// off by default, so that dropping never does network I/O behind the owner's back.
+    pub fn set_flush_on_drop(&mut self, flush_on_drop: bool) {
+        self.async_state.flush_on_drop = flush_on_drop;
+    }
+
+    /// The number of requests enqueued since the last execute().
+    pub fn pending_verifications(&self) -> usize {
+        self.async_state.pending.len()
     }
 
     /// Retrieves the version number of pkg and calls enq_verify_with_version with that version number.
-    pub fn enq_verify(&mut self, pkg:&str) {
+    pub fn enq_verify(&mut self, pkg:&str, use_cache: bool) -> Result<QueuedVerification, String> {
         let version = self.get_available_debver(pkg);
         match version {
-            None => { println!("Error: package {} not defined.", pkg); return },
-            Some(v) => { 
+            None => Err(format!("Error: package {} not defined.", pkg)),
+            Some(v) => {
                 let vs = &v.to_string();
-                self.enq_verify_with_version(pkg, vs); 
+                self.enq_verify_with_version(pkg, vs, use_cache)
             }
+        }
+    }
+
+    /// Enqueues a request for the provided version/package information. Stores any needed state to async_state so that execute() can handle the results.
+    /// The strongest checksum known locally is asked for; if the server doesn't have that one (404), the next strongest is.
+    /// If use_cache is set and the verification cache has a fresh hash from one of the servers, execute() uses that instead of sending the request.
+    pub fn enq_verify_with_version(&mut self, pkg:&str, version:&str, use_cache: bool) -> Result<QueuedVerification, String> {
+        let checksums : Vec<(ChecksumType, String)> = self.get_checksums(pkg).into_iter().map(|(algo, hash)| (algo, String::from(hash))).collect();
+        if checksums.is_empty() {
+            return Err(format!("Error: no checksum known for package {}.", pkg));
+        }
+        let path = format!("/rest/v1/checksums/{}/{}", pkg, urlencoding::encode(version));
+        let algos : Vec<ChecksumType> = checksums.iter().map(|(algo, _)| *algo).collect();
+        let cached = if use_cache { self.cached_hash(pkg, version, &algos) } else { None };
+        let mirrors = self.async_state.mirrors.lock().unwrap();
+        let mut pending = PendingRequest { package: String::from(pkg), version: String::from(version), path, checksums, cached: None };
+        let url = match &cached {
+            Some((server, algo, _)) => pending.url(server, *algo),
+            None => pending.url(mirrors.addr(mirrors.first_choice()), algos[0])
         };
+        let queued = QueuedVerification { package: String::from(pkg), version: String::from(version), url, cached: cached.is_some() };
+        pending.cached = cached;
+        drop(mirrors);
+        self.async_state.pending.push(pending);
+        Ok(queued)
     }
 
-    /// Enqueues a request for the provided version/package information. Stores any needed state to async_state so that execute() can handle the results and print out needed output.
-    pub fn enq_verify_with_version(&mut self, pkg:&str, version:&str) {
-        let url = format!("http://{}/rest/v1/checksums/{}/{}", self.async_state.server, pkg, urlencoding::encode(version));
-        println!("queueing request {}", url);
+    /// Verifies every installed package at its installed version: enqueues the requests and executes them
+    /// (with any requests enqueued before), calling progress whenever a request finishes.
+    /// Only the checksums of available versions are known, so installed packages that aren't available at their
+    /// installed version are skipped. Fresh cached hashes are used unless use_cache is false.
+    pub fn verify_installed(&mut self, use_cache: bool, progress: &mut dyn FnMut(&VerifyProgress)) -> Result<VerificationSummary, String> {
+        if self.installed_debvers.is_empty() {
+            return Err(String::from("no installed packages loaded"));
+        }
+        let (packages, skipped) : (Vec<_>, Vec<_>) = self.installed_debvers.iter()
+            .map(|(pn, v)| (String::from(self.get_package_name(pn)), v.to_string()))
+            .partition(|(name, v)| self.get_available_debver(name).is_some_and(|available| available.to_string() == *v));
+        let skipped = skipped.into_iter().map(|(name, _)| name).collect();
+        Ok(self.verify_packages(packages, skipped, use_cache, progress))
+    }
 
-        let mut easy = Easy2::new(Collector(Box::new(String::from(""))));
-        easy.url(&*url).unwrap();
-        easy.verbose(false).unwrap();
-        let handle = self.async_state.multi.add2(easy).unwrap();
-        self.async_state.easys.push(handle);
+    /// Like verify_installed(), for every available package at its available version.
+    pub fn verify_all(&mut self, use_cache: bool, progress: &mut dyn FnMut(&VerifyProgress)) -> Result<VerificationSummary, String> {
+        if self.available_debvers.is_empty() {
+            return Err(String::from("no available packages loaded"));
+        }
+        let packages = self.available_debvers.iter().map(|(pn, v)| (String::from(self.get_package_name(pn)), v.to_string())).collect();
+        Ok(self.verify_packages(packages, vec![], use_cache, progress))
+    }
 
-        let easy_num = EASYKEY_COUNTER.load(Ordering::SeqCst);
-        self.async_state.easy_num_to_pkg_name.insert(easy_num, String::from(pkg));
-        self.async_state.easy_num_to_version.insert(easy_num, String::from(version));
-        self.async_state.easy_num_to_md5sum.insert(easy_num, String::from(self.get_md5sum(pkg).unwrap()));
-        EASYKEY_COUNTER.fetch_add(1, Ordering::SeqCst);
+    /// Like verify_all(), for the available packages whose name matches regex.
+    pub fn verify_matching(&mut self, regex: &str, use_cache: bool, progress: &mut dyn FnMut(&VerifyProgress)) -> Result<VerificationSummary, String> {
+        let regex = Regex::new(regex).map_err(|e| format!("bad regex: {}", e))?;
+        let packages = self.available_debvers.iter()
+            .map(|(pn, v)| (self.get_package_name(pn), v))
+            .filter(|(name, _)| regex.is_match(name))
+            .map(|(name, v)| (String::from(name), v.to_string()))
+            .collect();
+        Ok(self.verify_packages(packages, vec![], use_cache, progress))
     }
 
-    /// Asks curl to perform all enqueued requests. For requests that succeed with response code 200, compares received MD5sum with local MD5sum (perhaps stored earlier). For requests that fail with 400+, prints error message.
-    pub fn execute(&mut self) {
-        self.async_state.multi.pipelining(true, true).unwrap();
-        while self.async_state.multi.perform().unwrap() > 0 {
-            self.async_state.multi.wait(&mut [], Duration::from_millis(500)).unwrap();
+    // skipped are the packages already known to have no local checksum
+    fn verify_packages(&mut self, mut packages: Vec<(String, String)>, mut skipped: Vec<String>, use_cache: bool, progress: &mut dyn FnMut(&VerifyProgress)) -> VerificationSummary {
+        packages.sort_unstable();
+        skipped.extend(packages.into_iter()
+            .filter_map(|(package, version)| self.enq_verify_with_version(&package, &version, use_cache).err().map(|_| package)));
+        skipped.sort_unstable();
+        let start = Instant::now();
+        let results = self.execute_with_progress(progress);
+        VerificationSummary::new(results, skipped, start.elapsed())
+    }
+
+    /// Asks curl to perform all enqueued requests, and returns one result per request, in the order they were enqueued.
+    /// For requests that succeed with response code 200, compares the received checksum with the local one of the same type;
+    /// any other response code is an HTTP error, and a request that got no response at all is a transport error.
+    /// Requests are sent and retried according to the VerifyPolicy, and the result is that of the last attempt.
+    /// When there are several servers, a mismatch is checked with the next server, and the result is that server's answer
+    /// if it gives one. A result is suspicious if another server has returned a different hash for the same package version.
+    pub fn execute(&mut self) -> Vec<VerificationResult> {
+        self.execute_with_progress(&mut |_| ())
+    }
+
+    /// Like execute(), calling progress before the first request is sent and whenever a request finishes.
+    /// Setting the interrupt_flag() cancels it: requests without a final result by then are reported as cancelled.
+    pub fn execute_with_progress(&mut self, progress: &mut dyn FnMut(&VerifyProgress)) -> Vec<VerificationResult> {
+        let pending = std::mem::take(&mut self.async_state.pending);
+        let state = &mut self.async_state;
+        state.interrupt.store(false, Ordering::Relaxed);
+        let results = run_transfers(&state.mirrors, &state.auth, &state.policy, pending, &state.interrupt, progress);
+        self.remember_results(&results);
+        results
+    }
+
+    /// Like execute(), but on a thread of its own, so that the database can be used while the requests are sent;
+    /// the results are collected with wait_jobs(). The job keeps using the servers, credentials and policy it was started with.
+    pub fn execute_in_background(&mut self) -> Result<JobStatus, String> {
+        if self.async_state.pending.is_empty() {
+            return Err(String::from("no enqueued requests to execute"));
         }
-        let mut easy_num = 0;
-        for easy_handle in self.async_state.easys.drain(..) {
-            let mut easy_after = self.async_state.multi.remove2(easy_handle).unwrap();
+        let state = &mut self.async_state;
+        let pending = std::mem::take(&mut state.pending);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(Mutex::new(VerifyProgress { total: pending.len(), ..VerifyProgress::default() }));
+        let thread = {
+            let (servers, auth, policy) = (Arc::clone(&state.mirrors), state.auth.clone(), state.policy.clone());
+            let (cancel, progress) = (Arc::clone(&cancel), Arc::clone(&progress));
+            std::thread::spawn(move || {
+                run_transfers(&servers, &auth, &policy, pending, &cancel, &mut |p| *progress.lock().unwrap() = p.clone())
+            })
+        };
+        let job = BackgroundJob { id: state.next_job, started: Instant::now(), cancel, progress, thread: Some(thread) };
+        state.next_job += 1;
+        let status = job.status();
+        state.jobs.push(job);
+        Ok(status)
+    }
 
-            let pkg_name = self.async_state.easy_num_to_pkg_name.get(&easy_num).unwrap();
-            let pkg_version = self.async_state.easy_num_to_version.get(&easy_num).unwrap();
-            let md5sum = self.async_state.easy_num_to_md5sum.get(&easy_num).unwrap();
+    /// How far the background jobs that haven't been waited for have got, in the order they were started.
+    pub fn jobs(&self) -> JobsResult {
+        JobsResult { jobs: self.async_state.jobs.iter().map(BackgroundJob::status).collect() }
+    }
 
-            let response_code = easy_after.response_code().unwrap();
-            if response_code == 200 {
-                let same_md5sum = md5sum == easy_after.get_ref().0.as_str();
-                println!("verifying {}, matches: {:?}", pkg_name, same_md5sum);
-            } else {
-                // Assume failed with 400+ error
-                println!("got error {} on request for package {} version {}", response_code, pkg_name, pkg_version);
+    /// Cancels background job id, or all of them if id is None; requests still being sent get a cancelled result.
+    /// Their results are still collected with wait_jobs().
+    pub fn cancel_jobs(&mut self, id: Option<usize>) -> Result<JobsResult, String> {
+        let ids = self.job_ids(id)?;
+        let jobs = self.async_state.jobs.iter().filter(|job| ids.contains(&job.id)).map(|job| {
+            job.cancel.store(true, Ordering::Relaxed);
+            job.status()
+        }).collect();
+        Ok(JobsResult { jobs })
+    }
+
+    /// Waits for background job id, or all of them if id is None, calling progress with their combined progress
+    /// every so often, and returns their results in the order the jobs were started. Setting the interrupt_flag()
+    /// cancels the jobs being waited for. A job whose thread panicked makes this return an error naming it,
+    /// though the hashes the other jobs got are still cached.
+    pub fn wait_jobs(&mut self, id: Option<usize>, progress: &mut dyn FnMut(&VerifyProgress)) -> Result<Vec<VerificationResult>, String> {
+        let ids = self.job_ids(id)?;
+        let state = &mut self.async_state;
+        state.interrupt.store(false, Ordering::Relaxed);
+        loop {
+            let waited : Vec<&BackgroundJob> = state.jobs.iter().filter(|job| ids.contains(&job.id)).collect();
+            if state.interrupt.load(Ordering::Relaxed) {
+                waited.iter().for_each(|job| job.cancel.store(true, Ordering::Relaxed));
+            }
+            let mut total = VerifyProgress::default();
+            for job in &waited {
+                let job_progress = job.progress.lock().unwrap();
+                total.done += job_progress.done;
+                total.total += job_progress.total;
+                total.mismatches += job_progress.mismatches;
+                total.errors += job_progress.errors;
+                total.elapsed = total.elapsed.max(job.started.elapsed());
+            }
+            if total.total > 0 {
+                progress(&total);
+            }
+            if waited.iter().all(|job| job.thread.as_ref().is_none_or(JoinHandle::is_finished)) {
+                break;
             }
-            easy_num += 1;
+            std::thread::sleep(POLL_INTERVAL);
         }
+        let (waited, rest) : (Vec<BackgroundJob>, Vec<BackgroundJob>) = std::mem::take(&mut state.jobs).into_iter().partition(|job| ids.contains(&job.id));
+        state.jobs = rest;
+        let mut results = vec![];
+        let mut failed = vec![];
+        for mut job in waited {
+            match job.thread.take().unwrap().join() {
+                Ok(job_results) => results.extend(job_results),
+                // e.g. curl failing to set up a request
+                Err(panic) => {
+                    let message = panic.downcast_ref::<&str>().copied().or_else(|| panic.downcast_ref::<String>().map(String::as_str));
+                    failed.push(format!("job {} failed: {}", job.id, message.unwrap_or("unknown error")));
+                }
+            }
+        }
+        // the results of the jobs that finished are kept even if another one failed
+        self.remember_results(&results);
+        if !failed.is_empty() {
+            return Err(failed.join("; "));
+        }
+        Ok(results)
+    }
 
-        // Clean up
-        EASYKEY_COUNTER.store(0, Ordering::Relaxed);
-        self.async_state.easy_num_to_pkg_name.clear();
-        self.async_state.easy_num_to_version.clear();
-        self.async_state.easy_num_to_md5sum.clear();
+    /// A flag that cancels the execute() or wait_jobs() in progress when it is set, e.g. by a Ctrl-C handler.
+    /// Each of them clears it when it starts.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.async_state.interrupt)
+    }
+
+    /// The jobs that cancel_jobs() or wait_jobs() with id apply to.
+    fn job_ids(&self, id: Option<usize>) -> Result<Vec<usize>, String> {
+        let jobs = &self.async_state.jobs;
+        match id {
+            None => Ok(jobs.iter().map(|job| job.id).collect()),
+            Some(id) if jobs.iter().any(|job| job.id == id) => Ok(vec![id]),
+            Some(id) => Err(format!("no background job {}", id))
+        }
+    }
+
+    /// Keeps the hashes received in the verification cache, and saves it.
+    fn remember_results(&mut self, results: &[VerificationResult]) {
+        // a hash another server disagrees with isn't worth keeping
+        for result in results.iter().filter(|r| !r.cached && !r.suspicious) {
+            if let Some(hash) = &result.remote_hash {
+                self.remember_hash(&result.server, &result.package, &result.version, result.algo, hash);
+            }
+        }
+        self.save_verify_cache();
+    }
+}
+
+/// Sends the requests of an execute(), in the foreground or on the thread of a background job, until they all have a final
+/// result or cancel is set. Returns one result per request, in order.
+fn run_transfers(servers: &Mutex<Mirrors>, auth: &HashMap<String, ServerAuth>, policy: &VerifyPolicy,
+                 pending: Vec<PendingRequest>, cancel: &AtomicBool, progress: &mut dyn FnMut(&VerifyProgress)) -> Vec<VerificationResult> {
+    let max_in_flight = policy.max_in_flight.max(1);
+    let start = Instant::now();
+    // curl handles can't be moved between threads, so each execute() has a Multi of its own rather than the Packages
+    let mut multi = Multi::new();
+    multi.pipelining(true, true).unwrap();
+    // keep the connections of all the requests in flight for reuse
+    multi.set_max_connects(max_in_flight).unwrap();
+    let mut transfers : Vec<Transfer> = pending.into_iter().map(|pending| {
+        Transfer { pending, easy: None, handle: None, attempts: 0, fallbacks: 0, server: 0, algo: 0, not_before: Instant::now(), second_opinion_of: None, result: None }
+    }).collect();
+    let mut status = VerifyProgress { done: 0, total: transfers.len(), mismatches: 0, errors: 0, elapsed: Duration::ZERO };
+    // requests ready to start, in order, and requests waiting to be retried; cached hashes need no request
+    let mut ready : VecDeque<usize> = VecDeque::new();
+    for (token, transfer) in transfers.iter_mut().enumerate() {
+        let (server, algo, hash) = match transfer.pending.cached.take() {
+            None => { ready.push_back(token); continue; }
+            Some(cached) => cached
+        };
+        let local_hash = transfer.pending.checksums.iter().find(|(a, _)| *a == algo).map(|(_, h)| h.clone()).unwrap();
+        let outcome = if hash == local_hash { VerificationOutcome::Match } else { VerificationOutcome::Mismatch };
+        if outcome == VerificationOutcome::Mismatch {
+            status.mismatches += 1;
+        }
+        status.done += 1;
+        transfer.result = Some(VerificationResult {
+            package: transfer.pending.package.clone(),
+            version: transfer.pending.version.clone(),
+            url: transfer.pending.url(&server, algo),
+            server,
+            http_status: 200,
+            algo,
+            remote_hash: Some(hash),
+            local_hash,
+            outcome,
+            suspicious: false,
+            conflicting_server: None,
+            conflicting_hash: None,
+            error: None,
+            cached: true,
+            attempts: 0,
+            latency_ms: 0.0,
+        });
+    }
+    let mut retries : Vec<usize> = vec![];
+    let mut in_flight = 0;
+    let mut next_start = Instant::now();
+    if status.total > 0 {
+        progress(&status);
+    }
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            let mirrors = servers.lock().unwrap();
+            for transfer in transfers.iter_mut().filter(|t| t.result.is_none()) {
+                if let Some(handle) = transfer.handle.take() {
+                    multi.remove2(handle).unwrap();
+                }
+                let (algo, local_hash) = transfer.pending.checksums[transfer.algo].clone();
+                transfer.result = Some(VerificationResult {
+                    package: transfer.pending.package.clone(),
+                    version: transfer.pending.version.clone(),
+                    url: transfer.pending.url(mirrors.addr(transfer.server), algo),
+                    server: String::from(mirrors.addr(transfer.server)),
+                    http_status: 0,
+                    algo,
+                    remote_hash: None,
+                    local_hash,
+                    outcome: VerificationOutcome::Cancelled,
+                    suspicious: false,
+                    conflicting_server: None,
+                    conflicting_hash: None,
+                    error: None,
+                    cached: false,
+                    attempts: transfer.attempts,
+                    latency_ms: 0.0,
+                });
+            }
+            break;
+        }
+        let now = Instant::now();
+        retries.retain(|token| {
+            let due = transfers[*token].not_before <= now;
+            if due { ready.push_back(*token); }
+            !due
+        });
+        // held while starting and finishing requests, but not while waiting for them
+        let mut mirrors = servers.lock().unwrap();
+        while in_flight < max_in_flight && next_start <= now {
+            let token = match ready.pop_front() {
+                None => break,
+                Some(token) => token
+            };
+            let transfer = &mut transfers[token];
+            let mut easy = transfer.easy.take().unwrap_or_else(|| Easy2::new(Collector::default()));
+            // the handle may have been used for another server before; resetting it keeps its connections
+            easy.reset();
+            easy.verbose(false).unwrap();
+            if transfer.attempts == 0 {
+                transfer.server = mirrors.first_choice();
+            }
+            let server = mirrors.addr(transfer.server);
+            easy.url(&transfer.pending.url(server, transfer.pending.checksums[transfer.algo].0)).unwrap();
+            if let Some(auth) = auth.get(server) {
+                auth.apply(&mut easy);
+            }
+            if let Some(ca_bundle) = &policy.ca_bundle {
+                easy.cainfo(ca_bundle).unwrap();
+            }
+            if let Some(proxy) = &policy.proxy {
+                easy.proxy(proxy).unwrap();
+            }
+            if let Some(no_proxy) = &policy.no_proxy {
+                easy.noproxy(no_proxy).unwrap();
+            }
+            *easy.get_mut() = Collector::default();
+            easy.connect_timeout(policy.connect_timeout).unwrap();
+            easy.timeout(policy.timeout).unwrap();
+            easy.tcp_keepalive(policy.keep_alive).unwrap();
+            easy.forbid_reuse(!policy.keep_alive).unwrap();
+            let mut handle = multi.add2(easy).unwrap();
+            handle.set_token(token).unwrap();
+            transfer.handle = Some(handle);
+            transfer.attempts += 1;
+            in_flight += 1;
+            next_start = next_start.max(now) + policy.start_interval();
+        }
+        // when the next request could be started, if there's room for it
+        let next_wake = if in_flight < max_in_flight {
+            let next_ready = if ready.is_empty() { None } else { Some(next_start) };
+            let next_retry = retries.iter().map(|token| transfers[*token].not_before.max(next_start)).min();
+            next_ready.into_iter().chain(next_retry).min()
+        } else {
+            None
+        };
+        if in_flight == 0 {
+            drop(mirrors);
+            match next_wake {
+                None => break,
+                // curl doesn't wait when it has nothing to wait for
+                Some(next_wake) => { std::thread::sleep(next_wake.saturating_duration_since(now).min(POLL_INTERVAL)); continue; }
+            }
+        }
+
+        multi.perform().unwrap();
+        let mut finished = vec![];
+        multi.messages(|message| {
+            if let (Ok(token), Some(result)) = (message.token(), message.result()) {
+                finished.push((token, result));
+            }
+        });
+        if finished.is_empty() {
+            drop(mirrors);
+            let wait = next_wake.map_or(POLL_INTERVAL, |w| w.saturating_duration_since(now).min(POLL_INTERVAL));
+            multi.wait(&mut [], wait).unwrap();
+            continue;
+        }
+        for (token, result) in finished {
+            in_flight -= 1;
+            let transfer = &mut transfers[token];
+            let easy = multi.remove2(transfer.handle.take().unwrap()).unwrap();
+            let response_code = easy.response_code().unwrap();
+            let latency = easy.total_time().unwrap_or_default();
+            let retryable = match &result {
+                Err(_) => true,
+                Ok(()) => response_code == 429 || (500..600).contains(&response_code)
+            };
+            mirrors.record(transfer.server, !retryable, latency);
+            let body = match (&result, response_code) {
+                (Ok(()), 200) => Some(String::from_utf8_lossy(&easy.get_ref().body).into_owned()),
+                _ => None
+            };
+            // servers that don't know ?algo= answer with the checksum they have, usually the MD5sum, so the length of
+            // the digest says which local checksum to compare it with
+            let answered_algo = body.as_deref().and_then(ChecksumType::of_digest);
+            if let Some(i) = answered_algo.and_then(|answered| transfer.pending.checksums.iter().position(|(algo, _)| *algo == answered)) {
+                transfer.algo = i;
+            }
+            let (algo, local_hash) = transfer.pending.checksums[transfer.algo].clone();
+            let unknown_algo = answered_algo.is_some_and(|answered| answered != algo);
+            let answer = match body {
+                Some(hash) if !unknown_algo => {
+                    let conflict = mirrors.record_hash(&transfer.pending.package, &transfer.pending.version, algo, transfer.server, &hash);
+                    Some(Answer { server: transfer.server, hash, latency, conflict })
+                }
+                Some(hash) => Some(Answer { server: transfer.server, hash, latency, conflict: None }),
+                None => None
+            };
+            let second_opinion_of = transfer.second_opinion_of.take();
+            if second_opinion_of.is_none() {
+                // the server doesn't have this checksum, or answered with one of a type that isn't known locally,
+                // so ask it for the next strongest one
+                let no_such_checksum = (result.is_ok() && response_code == 404) || unknown_algo;
+                if no_such_checksum && transfer.algo + 1 < transfer.pending.checksums.len() {
+                    transfer.algo += 1;
+                    transfer.fallbacks += 1;
+                    transfer.not_before = Instant::now();
+                    transfer.easy = Some(easy);
+                    retries.push(token);
+                    continue;
+                }
+                let tries = transfer.attempts - transfer.fallbacks;
+                if retryable && tries <= policy.max_retries {
+                    // servers that haven't been tried yet are tried right away
+                    transfer.not_before = Instant::now() + if (tries as usize) < mirrors.len() {
+                        Duration::ZERO
+                    } else {
+                        policy.retry_delay(tries, easy.get_ref().retry_after)
+                    };
+                    transfer.server = mirrors.next_after(transfer.server);
+                    transfer.easy = Some(easy);
+                    retries.push(token);
+                    continue;
+                }
+                if mirrors.len() > 1 && answer.as_ref().is_some_and(|a| a.hash != local_hash) {
+                    transfer.not_before = Instant::now();
+                    transfer.server = mirrors.next_after(transfer.server);
+                    transfer.second_opinion_of = answer;
+                    transfer.easy = Some(easy);
+                    retries.push(token);
+                    continue;
+                }
+            }
+
+            // the second opinion on a mismatch is final; if it isn't an answer, the mismatch stands
+            let answer = answer.or(second_opinion_of);
+            let outcome = match (&result, &answer) {
+                (_, Some(answer)) if answer.hash == local_hash => VerificationOutcome::Match,
+                (_, Some(_)) => VerificationOutcome::Mismatch,
+                (Err(_), None) => VerificationOutcome::TransportError,
+                (Ok(()), None) => VerificationOutcome::HttpError
+            };
+            let (server, http_status, latency, error) = match &answer {
+                Some(answer) => (answer.server, 200, answer.latency, None),
+                None => (transfer.server, response_code, latency, result.err().map(|e| String::from(e.description())))
+            };
+            let (remote_hash, conflict) = match answer {
+                Some(answer) => (Some(answer.hash), answer.conflict),
+                None => (None, None)
+            };
+            transfer.result = Some(VerificationResult {
+                package: transfer.pending.package.clone(),
+                version: transfer.pending.version.clone(),
+                url: transfer.pending.url(mirrors.addr(server), algo),
+                server: String::from(mirrors.addr(server)),
+                http_status,
+                algo,
+                remote_hash,
+                local_hash,
+                outcome,
+                suspicious: conflict.is_some(),
+                conflicting_server: conflict.as_ref().map(|(server, _)| server.clone()),
+                conflicting_hash: conflict.map(|(_, hash)| hash),
+                error,
+                cached: false,
+                attempts: transfer.attempts,
+                latency_ms: latency.as_micros() as f64 / 1000.0,
+            });
+            status.done += 1;
+            match outcome {
+                VerificationOutcome::Match | VerificationOutcome::Cancelled => (),
+                VerificationOutcome::Mismatch => status.mismatches += 1,
+                VerificationOutcome::HttpError | VerificationOutcome::TransportError => status.errors += 1,
+            }
+            status.elapsed = start.elapsed();
+            progress(&status);
+        }
     }
+    transfers.into_iter().map(|t| t.result.unwrap()).collect()
 }
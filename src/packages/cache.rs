@@ -0,0 +1,228 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::Packages;
+use crate::packages::results::{CacheClearResult, CacheEntry, CacheInfoResult};
+
+const CACHE_MAGIC : &[u8; 8] = b"RPKGCACH";
+/// Bump this whenever the layout of the cached stanzas changes; cache files with another version are ignored.
+const CACHE_FORMAT_VERSION : u32 = 5;
+const CACHE_EXTENSION : &str = "cache";
+
+/// What a cache file was made from; it's only used if the source file still has the same path, size and mtime.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct SourceKey {
+    path : String,
+    size : u64,
+    mtime_nanos : u128,
+}
+
+/// Written after the magic number and format version, and before the parsed stanzas.
+#[derive(Serialize, Deserialize)]
+struct CacheHeader {
+    kind : String,
+    source : SourceKey,
+}
+
+/// Where parsed files are cached: $RPKG_CACHE_DIR if it's set (caching is off if it's set but empty),
+/// otherwise $XDG_CACHE_HOME/rpkg or ~/.cache/rpkg.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("RPKG_CACHE_DIR") {
+        return if dir.is_empty() { None } else { Some(PathBuf::from(dir)) };
+    }
+    match (std::env::var_os("XDG_CACHE_HOME"), std::env::var_os("HOME")) {
+        (Some(xdg), _) if !xdg.is_empty() => Some(Path::new(&xdg).join("rpkg")),
+        (_, Some(home)) => Some(Path::new(&home).join(".cache").join("rpkg")),
+        _ => None
+    }
+}
+
+fn source_key(filename: &str) -> Option<SourceKey> {
+    let path = fs::canonicalize(filename).ok()?;
+    let metadata = fs::metadata(&path).ok()?;
+    Some(SourceKey {
+        path: path.to_string_lossy().into_owned(),
+        size: metadata.len(),
+        mtime_nanos: metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos(),
+    })
+}
+
+/// Reads the magic number, format version and header of a cache file.
+fn read_header(reader: &mut impl Read) -> Option<CacheHeader> {
+    let mut magic = [0u8; 8];
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut magic).ok()?;
+    reader.read_exact(&mut version).ok()?;
+    if &magic != CACHE_MAGIC || u32::from_le_bytes(version) != CACHE_FORMAT_VERSION {
+        return None;
+    }
+    bincode::deserialize_from(reader).ok()
+}
+
+/// Writes a cache file: its magic number and format version, then whatever write_contents serializes. The file is
+/// written under another name and renamed, so that an interrupted write can't leave a truncated cache behind.
+/// Failures are ignored, since caching is only an optimization.
+pub(super) fn write_cache_file(path: &Path, magic: &[u8; 8], format_version: u32,
+                               write_contents: impl FnOnce(&mut BufWriter<File>) -> bincode::Result<()>) {
+    let tmp_path = path.with_extension("tmp");
+    let write = || -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(magic)?;
+        writer.write_all(&format_version.to_le_bytes())?;
+        write_contents(&mut writer)?;
+        writer.flush()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    };
+    if write().is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+}
+
+impl Packages {
+    /// Sets where parsed files (and verified hashes) are cached, or turns caching off with None, which is the default;
+    /// the rpkg binary uses default_cache_dir().
+    pub fn set_cache_dir(&mut self, cache_dir: Option<PathBuf>) {
+        self.cache_dir = cache_dir;
+    }
+
+    // one cache file per source file and kind, named after a hash of both
+    fn cache_path(&self, source: &SourceKey, kind: &str) -> Option<PathBuf> {
+        let mut hasher = DefaultHasher::new();
+        (kind, &source.path).hash(&mut hasher);
+        self.cache_dir.as_ref().map(|dir| dir.join(format!("{}-{:016x}.{}", kind, hasher.finish(), CACHE_EXTENSION)))
+    }
+
+    /// Returns the stanzas cached for filename, if there's a cache file for it that is still valid.
+    pub(super) fn load_cached<T: DeserializeOwned>(&self, filename: &str, kind: &str) -> Option<Vec<T>> {
+        let source = source_key(filename)?;
+        // deserializing from memory is faster than from a reader
+        let bytes = fs::read(self.cache_path(&source, kind)?).ok()?;
+        let mut rest = &bytes[..];
+        let header = read_header(&mut rest)?;
+        if header.kind != kind || header.source != source {
+            return None;
+        }
+        bincode::deserialize(rest).ok()
+    }
+
+    /// Caches the stanzas parsed from filename. Caching is best-effort, so failures are silently ignored;
+    /// cache-info shows what got cached.
+    pub(super) fn store_cached<T: Serialize>(&self, filename: &str, kind: &str, stanzas: &[T]) {
+        let (source, path) = match source_key(filename).and_then(|s| self.cache_path(&s, kind).map(|p| (s, p))) {
+            None => return,
+            Some(sp) => sp
+        };
+        let header = CacheHeader { kind: String::from(kind), source };
+        write_cache_file(&path, CACHE_MAGIC, CACHE_FORMAT_VERSION, |writer| {
+            bincode::serialize_into(&mut *writer, &header)?;
+            bincode::serialize_into(writer, stanzas)
+        });
+    }
+
+    fn cache_files(&self) -> Vec<PathBuf> {
+        let dir = match &self.cache_dir {
+            None => return vec![],
+            Some(dir) => dir
+        };
+        let mut files : Vec<PathBuf> = fs::read_dir(dir).into_iter().flatten().map_while(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == CACHE_EXTENSION))
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Lists the cache files, with the file each was parsed from and whether it is still valid.
+    pub fn cache_info(&self) -> CacheInfoResult {
+        let entries = self.cache_files().into_iter().map(|path| {
+            let header = File::open(&path).ok().and_then(|f| read_header(&mut BufReader::new(f)));
+            CacheEntry {
+                size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                kind: header.as_ref().map(|h| h.kind.clone()),
+                // a file changed since it was cached, or deleted, makes the cache stale
+                valid: header.as_ref().is_some_and(|h| source_key(&h.source.path).is_some_and(|s| s == h.source)),
+                source: header.map(|h| h.source.path),
+                path: path.to_string_lossy().into_owned(),
+            }
+        }).collect();
+        CacheInfoResult {
+            cache_dir: self.cache_dir.as_ref().map(|d| d.to_string_lossy().into_owned()),
+            format_version: CACHE_FORMAT_VERSION,
+            entries,
+        }
+    }
+
+    /// Removes all the cache files.
+    pub fn cache_clear(&self) -> Result<CacheClearResult, String> {
+        let mut removed = 0;
+        for path in self.cache_files() {
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => return Err(format!("couldn't remove {}: {}", path.display(), e))
+            }
+        }
+        Ok(CacheClearResult { removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::tests::TestDir;
+
+    #[test]
+    fn cached_stanzas_round_trip_while_the_source_is_unchanged() {
+        let dir = TestDir::new("cache-round-trip");
+        let source = &dir.write("Packages", "Package: a\n");
+        let mut packages = Packages::new();
+        packages.set_cache_dir(Some(dir.path().join("cache")));
+
+        let stanzas = vec![String::from("a"), String::from("b")];
+        packages.store_cached(source, "packages", &stanzas);
+        assert_eq!(packages.load_cached::<String>(source, "packages"), Some(stanzas));
+        // another kind of file parsed from the same source has a cache file of its own
+        assert_eq!(packages.load_cached::<String>(source, "installed"), None);
+        assert!(packages.cache_info().entries[0].valid);
+
+        fs::write(source, "Package: a\nVersion: 1\n").unwrap();
+        assert_eq!(packages.load_cached::<String>(source, "packages"), None);
+        assert!(!packages.cache_info().entries[0].valid);
+    }
+
+    #[test]
+    fn parsing_from_the_cache_gives_the_same_packages() {
+        let dir = TestDir::new("cache-parse");
+        let source = &dir.write("Packages", "Package: a\nVersion: 1.0\nDepends: b (>= 2) | c\nDescription: an a\n\nPackage: b\nVersion: 2\n");
+        let parse = || {
+            let mut packages = Packages::new();
+            packages.set_cache_dir(Some(dir.path().join("cache")));
+            assert_eq!(packages.parse_packages(source).unwrap(), 2);
+            packages
+        };
+        let parsed = parse();
+        let cached = parse();
+        assert!(cached.load_cached::<crate::packages::parsers::AvailableStanza>(source, "packages").is_some());
+        assert_eq!(cached.deps("a").unwrap().depends, parsed.deps("a").unwrap().depends);
+        assert_eq!(cached.deps("a").unwrap().depends, vec!["b (>= 2) | c"]);
+        assert_eq!(cached.info("a").unwrap().metadata.unwrap().description.as_deref(), Some("an a"));
+    }
+
+    #[test]
+    fn files_of_another_format_version_are_ignored() {
+        let dir = TestDir::new("cache-version");
+        let path = dir.path().join("file.cache");
+        write_cache_file(&path, CACHE_MAGIC, CACHE_FORMAT_VERSION + 1, |_| Ok(()));
+        assert!(read_header(&mut File::open(&path).unwrap()).is_none());
+        // nothing is left behind but the file itself
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
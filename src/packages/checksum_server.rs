@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::Packages;
+use crate::packages::checksums::ChecksumType;
+use crate::packages::results::ChecksumServerStats;
+
+/// How a checksum server misbehaves, so that the verifier can be tested against something less reliable than localhost.
+/// Each request independently gets an injected error with probability error_rate, or else a wrong hash with
+/// probability wrong_hash_rate, and is answered after latency plus a uniformly random part of jitter.
+#[derive(Clone)]
+pub struct ServeOptions {
+    pub latency : Duration,
+    pub jitter : Duration,
+    pub error_rate : f64,
+    /// the status of injected errors
+    pub error_status : u16,
+    pub wrong_hash_rate : f64,
+    /// for the random choices, so that runs can be repeated
+    pub seed : u64,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            latency : Duration::ZERO,
+            jitter : Duration::ZERO,
+            error_rate : 0.0,
+            error_status : 500,
+            wrong_hash_rate : 0.0,
+            seed : 0,
+        }
+    }
+}
+
+// how long a kept-alive connection may wait for its next request before the server closes it
+const IDLE_TIMEOUT : Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Counters {
+    requests : AtomicUsize,
+    served : AtomicUsize,
+    wrong_hashes : AtomicUsize,
+    injected_errors : AtomicUsize,
+    not_found : AtomicUsize,
+}
+
+/// What the threads of a server share.
+struct Shared {
+    // keyed by package and version
+    checksums : HashMap<(String, String), BTreeMap<ChecksumType, String>>,
+    options : ServeOptions,
+    rng : Mutex<StdRng>,
+    counters : Counters,
+    stopping : AtomicBool,
+}
+
+/// A checksum server running on a thread of its own, plus one thread per connection, until it is stopped or dropped.
+pub struct ChecksumServer {
+    addr : SocketAddr,
+    shared : Arc<Shared>,
+    thread : Option<JoinHandle<()>>,
+}
+
+impl ChecksumServer {
+    /// The host:port it's listening on, e.g. for set_server(); useful when it was started on port 0.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// The number of package versions it knows a checksum of.
+    pub fn checksums(&self) -> usize {
+        self.shared.checksums.len()
+    }
+
+    pub fn stats(&self) -> ChecksumServerStats {
+        let counters = &self.shared.counters;
+        ChecksumServerStats {
+            addr : self.addr(),
+            requests : counters.requests.load(Ordering::Relaxed),
+            served : counters.served.load(Ordering::Relaxed),
+            wrong_hashes : counters.wrong_hashes.load(Ordering::Relaxed),
+            injected_errors : counters.injected_errors.load(Ordering::Relaxed),
+            not_found : counters.not_found.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Blocks for as long as the server runs, which is until the process is killed.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+
+    /// Stops accepting connections and returns what was served; requests already received are still answered.
+    pub fn stop(mut self) -> ChecksumServerStats {
+        self.shut_down();
+        self.stats()
+    }
+
+    fn shut_down(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shared.stopping.store(true, Ordering::Relaxed);
+            // wake up the accepting thread so that it notices
+            let _ = TcpStream::connect(self.addr);
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl Drop for ChecksumServer {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+impl Packages {
+    /// Starts serving the checksums of the available packages at their available versions on addr (host:port),
+    /// with the same API as the checksum server the verifier talks to: GET /rest/v1/checksums/<package>/<version>
+    /// returns the MD5sum as the body, and ?algo=sha256 or ?algo=sha512 asks for that checksum instead;
+    /// an unknown package or version, or one without that checksum, gets 404. Connections are kept alive.
+    pub fn serve_checksums(&self, addr: &str, options: ServeOptions) -> Result<ChecksumServer, String> {
+        let checksums = self.available_debvers.iter()
+            .filter_map(|(pn, v)| self.checksums.get(pn).map(|checksums| ((String::from(self.get_package_name(pn)), v.to_string()), checksums.clone())))
+            .collect();
+        let listener = TcpListener::bind(addr).map_err(|e| format!("couldn't listen on {}: {}", addr, e))?;
+        let local_addr = listener.local_addr().map_err(|e| format!("couldn't listen on {}: {}", addr, e))?;
+        let shared = Arc::new(Shared {
+            checksums,
+            rng : Mutex::new(StdRng::seed_from_u64(options.seed)),
+            options,
+            counters : Counters::default(),
+            stopping : AtomicBool::new(false),
+        });
+        let thread = {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.stopping.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    // a connection that failed before it was accepted doesn't matter
+                    if let Ok(stream) = stream {
+                        let shared = shared.clone();
+                        std::thread::spawn(move || { let _ = serve_connection(stream, &shared); });
+                    }
+                }
+            })
+        };
+        Ok(ChecksumServer { addr: local_addr, shared, thread: Some(thread) })
+    }
+}
+
+/// Answers the requests on one connection until the client closes it, asks for it to be closed, or stays idle too long.
+fn serve_connection(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let mut keep_alive = request_line.trim_end().ends_with("HTTP/1.1");
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 {
+                return Ok(());
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("connection") && value.trim().eq_ignore_ascii_case("close") {
+                    keep_alive = false;
+                }
+            }
+        }
+        let (status, body) = answer(&request_line, shared);
+        // in one write, so that the response goes out in one packet
+        let response = format!("HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n{}\r\n{}",
+                               status, reason_phrase(status), body.len(), if keep_alive { "" } else { "Connection: close\r\n" }, body);
+        writer.write_all(response.as_bytes())?;
+        if !keep_alive || shared.stopping.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+    }
+}
+
+/// The status and body of the response to request_line, after the delay the options ask for.
+fn answer(request_line: &str, shared: &Shared) -> (u16, String) {
+    let options = &shared.options;
+    let counters = &shared.counters;
+    counters.requests.fetch_add(1, Ordering::Relaxed);
+    // the random choices are made as the requests arrive, so that they can be repeated by sending requests one at a time
+    let (inject_error, wrong_hash, delay) = {
+        let mut rng = shared.rng.lock().unwrap();
+        let inject_error = options.error_rate > 0.0 && rng.gen_bool(options.error_rate.min(1.0));
+        let wrong_hash = options.wrong_hash_rate > 0.0 && rng.gen_bool(options.wrong_hash_rate.min(1.0));
+        (inject_error, wrong_hash, options.latency + options.jitter.mul_f64(rng.gen::<f64>()))
+    };
+    std::thread::sleep(delay);
+    let checksum = parse_checksum_request(request_line)
+        .and_then(|(key, algo)| shared.checksums.get(&key).and_then(|checksums| checksums.get(&algo)));
+    match checksum {
+        _ if inject_error => {
+            counters.injected_errors.fetch_add(1, Ordering::Relaxed);
+            (options.error_status, String::from("injected error"))
+        }
+        None => {
+            counters.not_found.fetch_add(1, Ordering::Relaxed);
+            (404, String::from("not found"))
+        }
+        Some(hash) if wrong_hash => {
+            counters.wrong_hashes.fetch_add(1, Ordering::Relaxed);
+            (200, corrupt_hash(hash))
+        }
+        Some(hash) => {
+            counters.served.fetch_add(1, Ordering::Relaxed);
+            (200, hash.clone())
+        }
+    }
+}
+
+/// The package and version, and the type of checksum, asked for by
+/// GET /rest/v1/checksums/<package>/<url-encoded version>[?algo=<type>] HTTP/1.x.
+fn parse_checksum_request(request_line: &str) -> Option<((String, String), ChecksumType)> {
+    let mut words = request_line.split_whitespace();
+    if words.next()? != "GET" {
+        return None;
+    }
+    let target = words.next()?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None)
+    };
+    let algo = match query {
+        None => ChecksumType::Md5,
+        Some(query) => query.strip_prefix("algo=")?.parse().ok()?
+    };
+    let (package, version) = path.strip_prefix("/rest/v1/checksums/")?.split_once('/')?;
+    Some(((String::from(package), urlencoding::decode(version).ok()?.into_owned()), algo))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    }
+}
+
+// a hash that's different from hash in every hex digit
+fn corrupt_hash(hash: &str) -> String {
+    hash.chars().map(|c| c.to_digit(16).map_or(c, |d| std::char::from_digit(15 - d, 16).unwrap())).collect()
+}
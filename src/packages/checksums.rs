@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+/// A hash algorithm that package checksums are given in, from weakest to strongest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumType {
+    Md5,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumType {
+    /// The field of a Packages stanza that gives a checksum of this type.
+    pub fn field(self) -> &'static str {
+        match self {
+            ChecksumType::Md5 => "MD5sum",
+            ChecksumType::Sha256 => "SHA256",
+            ChecksumType::Sha512 => "SHA512",
+        }
+    }
+
+    /// The type of checksum a Packages stanza field gives, if it gives one.
+    pub fn from_field(key: &str) -> Option<ChecksumType> {
+        match key {
+            "MD5sum" => Some(ChecksumType::Md5),
+            "SHA256" => Some(ChecksumType::Sha256),
+            "SHA512" => Some(ChecksumType::Sha512),
+            _ => None
+        }
+    }
+
+    /// The type of checksum a hex digest is, going by its length.
+    pub fn of_digest(digest: &str) -> Option<ChecksumType> {
+        if !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        match digest.len() {
+            32 => Some(ChecksumType::Md5),
+            64 => Some(ChecksumType::Sha256),
+            128 => Some(ChecksumType::Sha512),
+            _ => None
+        }
+    }
+}
+
+impl FromStr for ChecksumType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "md5" => Ok(ChecksumType::Md5),
+            "sha256" => Ok(ChecksumType::Sha256),
+            "sha512" => Ok(ChecksumType::Sha512),
+            _ => Err(format!("unknown checksum type {:?}: expected md5, sha256 or sha512", s))
+        }
+    }
+}
+
+impl fmt::Display for ChecksumType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ChecksumType::Md5 => "md5",
+            ChecksumType::Sha256 => "sha256",
+            ChecksumType::Sha512 => "sha512",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Reads reader to the end, hashing everything with each of algos; returns the number of bytes read and the
+/// hex digests, in the order of algos.
+pub fn hash_reader(mut reader: impl Read, algos: &[ChecksumType]) -> io::Result<(u64, Vec<String>)> {
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    let mut sha512 = Sha512::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        };
+        size += n as u64;
+        for algo in algos {
+            match algo {
+                ChecksumType::Md5 => md5.update(&buf[..n]),
+                ChecksumType::Sha256 => sha256.update(&buf[..n]),
+                ChecksumType::Sha512 => sha512.update(&buf[..n]),
+            }
+        }
+    }
+    let (md5, sha256, sha512) = (hex(&md5.finalize()), hex(&sha256.finalize()), hex(&sha512.finalize()));
+    let digests = algos.iter().map(|algo| match algo {
+        ChecksumType::Md5 => md5.clone(),
+        ChecksumType::Sha256 => sha256.clone(),
+        ChecksumType::Sha512 => sha512.clone(),
+    }).collect();
+    Ok((size, digests))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
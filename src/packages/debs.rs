@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use crate::Packages;
+use crate::debversion::DebianVersionNum;
+use crate::packages::checksums::{self, ChecksumType};
+use crate::packages::interner::{Interner, PackageId, PackageMap};
+use crate::packages::metadata::PackageMetadata;
+use crate::packages::results::{DebMatch, DebOutcome, DebPoolReport, DebVerification};
+
+const AR_MAGIC : &[u8] = b"!<arch>\n";
+const AR_HEADER_LEN : usize = 60;
+const TAR_BLOCK_LEN : usize = 512;
+// a control file is a few KB at most; this keeps a bogus size in a damaged archive from exhausting memory
+const MAX_CONTROL_LEN : u64 = 64 * 1024 * 1024;
+
+/// What verifying .deb files needs from Packages; unlike Packages, it can be shared between threads.
+struct DebIndex<'a> {
+    interner : &'a Interner,
+    checksums : &'a PackageMap<BTreeMap<ChecksumType, String>>,
+    metadata : &'a PackageMap<PackageMetadata>,
+    debvers : &'a PackageMap<DebianVersionNum>,
+    // the last component of each Filename -> the packages with that Filename
+    by_file_name : HashMap<&'a str, Vec<PackageId>>,
+}
+
+impl<'a> DebIndex<'a> {
+    fn filename(&self, package_num: PackageId) -> Option<&'a str> {
+        self.metadata.get(package_num).and_then(|m| m.filename.as_deref())
+    }
+
+    /// The package whose Filename is path, where path may be relative to the root of the archive or to any
+    /// directory under it, or absolute.
+    fn find_by_filename(&self, path: &Path) -> Option<PackageId> {
+        let file_name = path.file_name()?.to_str()?;
+        let candidates = self.by_file_name.get(file_name)?;
+        // two Filenames with the same last component are told apart by as many directories as path has
+        let best = candidates.iter().max_by_key(|package_num| {
+            let filename = Path::new(self.filename(**package_num).unwrap());
+            filename.components().rev().zip(path.components().rev()).take_while(|(a, b)| a == b).count()
+        });
+        best.copied()
+    }
+
+    /// The available package with the given name and version.
+    fn find_by_control(&self, package: &str, version: &str) -> Option<PackageId> {
+        let package_num = self.interner.get(package)?;
+        let available = self.debvers.get(package_num)?;
+        let version = version.parse::<DebianVersionNum>().ok()?;
+        if *available == version { Some(package_num) } else { None }
+    }
+
+    /// Hashes the file at path and compares it with the index. Files that can't be found by file name are
+    /// looked up by the package and version in their control data.
+    fn verify(&self, path: &Path, relative: &Path) -> DebVerification {
+        let mut result = DebVerification {
+            path: Some(path.to_string_lossy().into_owned()),
+            package: None, version: None, filename: None, matched_by: None, outcome: DebOutcome::Unknown,
+            size: None, expected_size: None, checked: vec![], mismatched: vec![], error: None,
+        };
+        let found = match self.find_by_filename(relative) {
+            Some(package_num) => Some((package_num, DebMatch::Filename)),
+            None => match read_control(path) {
+                Ok((package, version)) => {
+                    let found = self.find_by_control(&package, &version).map(|package_num| (package_num, DebMatch::Control));
+                    result.package = Some(package);
+                    result.version = Some(version);
+                    found
+                }
+                Err(e) => { result.error = Some(e); None }
+            }
+        };
+        let package_num = match found {
+            None => return result,
+            Some((package_num, matched_by)) => { result.matched_by = Some(matched_by); package_num }
+        };
+        result.package = Some(String::from(self.interner.name(package_num)));
+        result.version = self.debvers.get(package_num).map(|v| v.to_string());
+        result.filename = self.filename(package_num).map(String::from);
+        result.expected_size = self.metadata.get(package_num).and_then(|m| m.size);
+        result.error = None;
+        let expected : Vec<(ChecksumType, &String)> = self.checksums.get(package_num).into_iter().flatten().map(|(a, h)| (*a, h)).collect();
+        let algos : Vec<ChecksumType> = expected.iter().map(|(algo, _)| *algo).collect();
+        let hashed = File::open(path).and_then(|file| checksums::hash_reader(BufReader::new(file), &algos));
+        let (size, digests) = match hashed {
+            Ok(hashed) => hashed,
+            Err(e) => {
+                result.outcome = DebOutcome::Corrupt;
+                result.error = Some(format!("couldn't read it: {}", e));
+                return result;
+            }
+        };
+        result.size = Some(size);
+        result.checked = algos;
+        result.mismatched = expected.iter().zip(&digests)
+            .filter(|((_, hash), digest)| !hash.eq_ignore_ascii_case(digest))
+            .map(|((algo, _), _)| *algo)
+            .collect();
+        let size_matches = result.expected_size.is_none_or(|expected_size| expected_size == size);
+        result.outcome = if size_matches && result.mismatched.is_empty() { DebOutcome::Ok } else { DebOutcome::Corrupt };
+        result
+    }
+}
+
+impl Packages {
+    fn deb_index(&self) -> DebIndex<'_> {
+        let mut by_file_name : HashMap<&str, Vec<PackageId>> = HashMap::new();
+        for (package_num, metadata) in self.available_metadata.iter() {
+            if let Some(file_name) = metadata.filename.as_deref().and_then(|f| Path::new(f).file_name()).and_then(|f| f.to_str()) {
+                by_file_name.entry(file_name).or_default().push(package_num);
+            }
+        }
+        DebIndex {
+            interner: &self.interner,
+            checksums: &self.checksums,
+            metadata: &self.available_metadata,
+            debvers: &self.available_debvers,
+            by_file_name,
+        }
+    }
+
+    /// Hashes a .deb file and compares its size and checksums with those of the available package it is:
+    /// the one whose Filename it has, or else the one named in its control data.
+    pub fn verify_deb(&self, path: &str) -> Result<DebVerification, String> {
+        let path = Path::new(path);
+        if !path.is_file() {
+            return Err(format!("couldn't read {}: not a file", path.display()));
+        }
+        Ok(self.deb_index().verify(path, path))
+    }
+
+    /// Verifies every .deb file under dir, which is the root of an archive or its pool directory, in parallel,
+    /// like verify_deb; available packages whose Filename wasn't found there are missing.
+    pub fn verify_pool(&self, dir: &str) -> Result<DebPoolReport, String> {
+        let start = Instant::now();
+        let mut files = vec![];
+        find_debs(Path::new(dir), &mut files).map_err(|e| format!("couldn't read {}: {}", dir, e))?;
+        files.sort_unstable();
+        let index = self.deb_index();
+        let mut results : Vec<DebVerification> = files.par_iter()
+            .map(|path| index.verify(path, path.strip_prefix(dir).unwrap_or(path)))
+            .collect();
+        let found : HashSet<(&str, &str)> = results.iter()
+            .filter_map(|r| r.matched_by.and(r.package.as_deref()).zip(r.version.as_deref()))
+            .collect();
+        let mut missing : Vec<DebVerification> = vec![];
+        for (package_num, metadata) in self.available_metadata.iter() {
+            let (filename, version) = match (&metadata.filename, self.available_debvers.get(package_num)) {
+                (Some(filename), Some(version)) => (filename, version.to_string()),
+                _ => continue
+            };
+            let package = self.interner.name(package_num);
+            if found.contains(&(package, &version[..])) {
+                continue;
+            }
+            missing.push(DebVerification {
+                path: None, package: Some(String::from(package)), version: Some(version), filename: Some(filename.clone()),
+                matched_by: None, outcome: DebOutcome::Missing, size: None, expected_size: metadata.size,
+                checked: vec![], mismatched: vec![], error: None,
+            });
+        }
+        missing.sort_unstable_by(|a, b| a.filename.cmp(&b.filename));
+        let count = |outcome| results.iter().filter(|r| r.outcome == outcome).count();
+        let (ok, corrupt, unknown) = (count(DebOutcome::Ok), count(DebOutcome::Corrupt), count(DebOutcome::Unknown));
+        let bytes = results.iter().filter_map(|r| r.size).sum();
+        let missing_count = missing.len();
+        results.extend(missing);
+        Ok(DebPoolReport {
+            dir: String::from(dir),
+            files: files.len(),
+            ok, corrupt, unknown,
+            missing: missing_count,
+            bytes,
+            elapsed_ms: start.elapsed().as_micros() as f64 / 1000.0,
+            results,
+        })
+    }
+}
+
+/// Adds the .deb files under dir to files, recursively; symbolic links to directories aren't followed.
+fn find_debs(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_debs(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "deb") && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The Package and Version fields of the control file in the .deb at path.
+fn read_control(path: &Path) -> Result<(String, String), String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| format!("couldn't read it: {}", e))?);
+    let (name, member) = read_control_member(&mut reader).map_err(|e| format!("not a .deb: {}", e))?;
+    let tar = decompress(&name, member).map_err(|e| format!("couldn't decompress its {}: {}", name, e))?;
+    let control = tar_entry(&tar, "control").ok_or_else(|| format!("no control file in its {}", name))?;
+    let control = String::from_utf8_lossy(control);
+    let field = |key: &str| control.lines().find_map(|l| l.strip_prefix(key)?.strip_prefix(':').map(|v| String::from(v.trim())));
+    match (field("Package"), field("Version")) {
+        (Some(package), Some(version)) => Ok((package, version)),
+        _ => Err(String::from("its control file has no Package or Version"))
+    }
+}
+
+/// Finds the control.tar[.gz|.xz|.zst] member of an ar archive, and returns its name and contents.
+fn read_control_member(reader: &mut impl Read) -> io::Result<(String, Vec<u8>)> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what);
+    let mut magic = [0; 8];
+    if reader.read_exact(&mut magic).is_err() || magic != AR_MAGIC {
+        return Err(invalid("not an ar archive"));
+    }
+    loop {
+        let mut header = [0; AR_HEADER_LEN];
+        match reader.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(invalid("no control.tar member")),
+            other => other?
+        }
+        let name = String::from_utf8_lossy(&header[..16]).trim_end().trim_end_matches('/').to_string();
+        let size : u64 = String::from_utf8_lossy(&header[48..58]).trim().parse().map_err(|_| invalid("bad member size"))?;
+        if name.starts_with("control.tar") {
+            if size > MAX_CONTROL_LEN {
+                return Err(invalid("control.tar member too large"));
+            }
+            let mut member = vec![];
+            reader.take(size).read_to_end(&mut member)?;
+            return Ok((name, member));
+        }
+        // members are padded to an even length
+        io::copy(&mut reader.take(size + size % 2), &mut io::sink())?;
+    }
+}
+
+fn decompress(name: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    match name.strip_prefix("control.tar").unwrap() {
+        "" => return Ok(data),
+        ".gz" => flate2::read::GzDecoder::new(&data[..]).take(MAX_CONTROL_LEN).read_to_end(&mut out).map(|_| ()).map_err(|e| e.to_string())?,
+        ".xz" => lzma_rs::xz_decompress(&mut &data[..], &mut out).map_err(|e| e.to_string())?,
+        ".zst" => ruzstd::StreamingDecoder::new(&data[..]).map_err(|e| e.to_string())?
+            .take(MAX_CONTROL_LEN).read_to_end(&mut out).map(|_| ()).map_err(|e| e.to_string())?,
+        other => return Err(format!("unknown compression {}", other))
+    }
+    Ok(out)
+}
+
+/// The contents of the regular file called name (or ./name) in a tar archive.
+fn tar_entry<'t>(tar: &'t [u8], name: &str) -> Option<&'t [u8]> {
+    let mut offset = 0;
+    while offset + TAR_BLOCK_LEN <= tar.len() {
+        let header = &tar[offset..offset + TAR_BLOCK_LEN];
+        // the archive ends with zero blocks
+        if header.iter().all(|b| *b == 0) {
+            return None;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let bytes = &header[range];
+            String::from_utf8_lossy(&bytes[..bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len())]).into_owned()
+        };
+        let size = usize::from_str_radix(field(124..136).trim(), 8).ok()?;
+        let entry_name = field(0..100);
+        let is_file = header[156] == b'0' || header[156] == 0;
+        let data_start = offset + TAR_BLOCK_LEN;
+        if is_file && entry_name.trim_start_matches("./") == name {
+            return tar.get(data_start..data_start + size);
+        }
+        offset = data_start + size.div_ceil(TAR_BLOCK_LEN) * TAR_BLOCK_LEN;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a ustar header for an entry; tar_entry doesn't look at the checksum
+    fn tar_header(name: &str, size: usize, type_flag: u8) -> Vec<u8> {
+        let mut header = vec![0u8; TAR_BLOCK_LEN];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}", size);
+        header[124..124 + size.len()].copy_from_slice(size.as_bytes());
+        header[156] = type_flag;
+        header
+    }
+
+    fn tar(entries: &[(&str, &[u8], u8)]) -> Vec<u8> {
+        let mut tar = vec![];
+        for (name, contents, type_flag) in entries {
+            tar.extend(tar_header(name, contents.len(), *type_flag));
+            tar.extend_from_slice(contents);
+            tar.resize(tar.len().div_ceil(TAR_BLOCK_LEN) * TAR_BLOCK_LEN, 0);
+        }
+        tar.extend(vec![0u8; 2 * TAR_BLOCK_LEN]);
+        tar
+    }
+
+    #[test]
+    fn tar_entry_finds_files_with_or_without_dot_slash() {
+        let control = b"Package: foo\nVersion: 1.0\n";
+        let md5sums = vec![b'x'; 700];
+        let tar = tar(&[("./", b"", b'5'), ("./md5sums", &md5sums, b'0'), ("./control", control, b'0')]);
+        assert_eq!(tar_entry(&tar, "control"), Some(&control[..]));
+        assert_eq!(tar_entry(&tar, "md5sums"), Some(&md5sums[..]));
+        assert_eq!(tar_entry(&tar, "postinst"), None);
+        // directories aren't files
+        assert_eq!(tar_entry(&tar, ""), None);
+    }
+
+    #[test]
+    fn tar_entry_stops_at_truncated_archives() {
+        let tar = tar(&[("control", b"Package: foo\n", b'0')]);
+        assert_eq!(tar_entry(&tar[..TAR_BLOCK_LEN + 4], "control"), None);
+        assert_eq!(tar_entry(&tar[..100], "control"), None);
+    }
+}
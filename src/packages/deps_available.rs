@@ -1,54 +1,123 @@
-use rpkg::debversion;
-use rpkg::debversion::{DebianVersionNum, VersionRelation};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::debversion;
 use crate::Packages;
-use crate::packages::Dependency;
+use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::interner::PackageId;
+use crate::packages::results::{BrokenPackage, BrokenTarget, CheckResult, DependencyStatus, DepsAvailableResult};
 
 impl Packages {
-    /// Gets the dependencies of package_name, and prints out whether they are satisfied (and by which library/version) or not.
-    pub fn deps_available(&self, package_name: &str) {
+    /// Gets the dependencies of package_name, and whether they are satisfied (and by which library/version) or not.
+    pub fn deps_available(&self, package_name: &str) -> Result<DepsAvailableResult, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package {}:", package_name);
         // some sort of for loop...
         let dependencies = self.dependencies.get(self.get_package_num(package_name)).unwrap();
+        let mut statuses = vec![];
         for dep in  dependencies {
-            println!("- dependency {:?}", self.dep2str(dep));
-            match self.dep_is_satisfied(dep) {
-                None => {
-                    println!("-> not satisfied");
+            let satisfied_by = self.dep_is_satisfied(dep);
+            statuses.push(DependencyStatus {
+                dependency: self.dep2str(dep),
+                satisfied_by: satisfied_by.map(String::from),
+                installed_version: satisfied_by.map(|p| self.installed_debvers.get(self.get_package_num(p)).unwrap().to_string()),
+            });
+        }
+        Ok(DepsAvailableResult { package: String::from(package_name), dependencies: statuses })
+    }
+
+    /// Checks the dependencies and conflicts of every installed package against the installed set, like apt-get check.
+    /// Returns the broken packages grouped by the missing (or conflicting) target.
+    pub fn check(&self) -> CheckResult {
+        let mut unmet : BTreeMap<String, Vec<BrokenPackage>> = BTreeMap::new();
+        let mut conflicting : BTreeMap<String, Vec<BrokenPackage>> = BTreeMap::new();
+        let mut broken_packages = HashSet::new();
+        let mut installed : Vec<(&str, PackageId)> = self.installed_debvers.keys().map(|pn| (self.get_package_name(pn), pn)).collect();
+        installed.sort_unstable();
+        for (package_name, package_num) in installed {
+            let installed_version = self.installed_debvers.get(package_num).unwrap();
+            if let Some(deps) = self.installed_dependencies.get(package_num) {
+                for dep in deps {
+                    if self.dep_is_satisfied(dep).is_none() {
+                        let target = dep.iter().map(|d| self.get_package_name(d.package_num)).collect::<Vec<&str>>().join(" | ");
+                        unmet.entry(target).or_default().push(BrokenPackage {
+                            package: String::from(package_name),
+                            version: installed_version.to_string(),
+                            clause: self.dep2str(dep),
+                            conflicting_version: None,
+                        });
+                        broken_packages.insert(package_num);
+                    }
                 }
-                Some(package_name) => {
-                    println!("+ {} satisfied by installed version {}", package_name, self.installed_debvers.get(self.get_package_num(package_name)).unwrap());
+            }
+            if let Some(conflicts) = self.installed_conflicts.get(package_num) {
+                for conflict in conflicts {
+                    for alternative in conflict {
+                        // packages routinely conflict with virtual packages they provide themselves
+                        if let Some(conflicting_package) = self.alternative_satisfied_by(alternative).filter(|p| *p != package_num) {
+                            conflicting.entry(String::from(self.get_package_name(conflicting_package))).or_default().push(BrokenPackage {
+                                package: String::from(package_name),
+                                version: installed_version.to_string(),
+                                clause: self.dep2str(conflict),
+                                conflicting_version: Some(self.installed_debvers.get(conflicting_package).unwrap().to_string()),
+                            });
+                            broken_packages.insert(package_num);
+                        }
+                    }
                 }
             }
         }
+
+        let problems : usize = unmet.values().chain(conflicting.values()).map(|r| r.len()).sum();
+        let targets = |m: BTreeMap<String, Vec<BrokenPackage>>| m.into_iter().map(|(target, packages)| BrokenTarget { target, packages }).collect();
+        CheckResult {
+            unmet: targets(unmet),
+            conflicting: targets(conflicting),
+            broken_packages: broken_packages.len(),
+            problems,
+        }
     }
 
     /// Returns Some(package) which satisfies dependency dd, or None if not satisfied.
     pub fn dep_is_satisfied(&self, dd:&Dependency) -> Option<&str> {
         // presumably you should loop on dd
         for alternative in dd {
-            if self.installed_debvers.contains_key(&alternative.package_num) {
-                match &alternative.rel_version {
-                    None => {
-                        // Dependency has no version requirement, so any installed version of the dependency is satisfactory
-                        return Some(self.get_package_name(alternative.package_num));
-                    }
-                    Some((required_version_relation, required_version)) => {
-                        // Dependency has version requirement, compare versions
-                        let required_v = required_version.parse::<debversion::DebianVersionNum>().unwrap();
-                        let installed_v = self.installed_debvers.get(&alternative.package_num).unwrap();
-                        if debversion::cmp_debversion_with_op(&required_version_relation, installed_v, &required_v) {
-                            return Some(self.get_package_name(alternative.package_num));
-                        }
-                        // Else, move on to next alternative
-                    }
+            if let Some(package_num) = self.alternative_satisfied_by(alternative) {
+                return Some(self.get_package_name(package_num));
+            }
+            // Else, move on to next alternative
+        }
+        None
+    }
+
+    /// Returns the installed package satisfying alternative, if any: either the named package itself at a version
+    /// meeting the version requirement, or an installed package that Provides it.
+    fn alternative_satisfied_by(&self, alternative: &RelVersionedPackageNum) -> Option<PackageId> {
+        match (self.installed_debvers.get(alternative.package_num), &alternative.rel_version) {
+            // Dependency has no version requirement, so any installed version of the dependency is satisfactory
+            (Some(_), None) => return Some(alternative.package_num),
+            (Some(installed_v), Some((required_version_relation, required_version))) => {
+                // Dependency has version requirement, compare versions
+                let required_v = required_version.parse::<debversion::DebianVersionNum>().unwrap();
+                if debversion::cmp_debversion_with_op(required_version_relation, installed_v, &required_v) {
+                    return Some(alternative.package_num);
                 }
             }
+            (None, _) => {}
         }
-        return None;
+        let providers = self.installed_provides.get(alternative.package_num)?;
+        providers.iter().find(|(_, provided_version)| {
+            match (&alternative.rel_version, provided_version) {
+                (None, _) => true,
+                // An unversioned Provides only satisfies unversioned dependencies
+                (Some(_), None) => false,
+                (Some((required_version_relation, required_version)), Some(provided_version)) => {
+                    let required_v = required_version.parse::<debversion::DebianVersionNum>().unwrap();
+                    let provided_v = provided_version.parse::<debversion::DebianVersionNum>().unwrap();
+                    debversion::cmp_debversion_with_op(required_version_relation, &provided_v, &required_v)
+                }
+            }
+        }).map(|(provider, _)| *provider)
     }
 
     /// Returns a Vec of packages which would satisfy dependency dd but for the version.
@@ -58,13 +127,13 @@ impl Packages {
         let mut result = vec![];
         // another loop on dd
         for alternative in dd {
-            if self.installed_debvers.contains_key(&alternative.package_num) {
+            if self.installed_debvers.contains_key(alternative.package_num) {
                 // We can assume this means that the dependency has a version requirement that is not met by the installed version, no need to check
                 result.push(self.get_package_name(alternative.package_num));
             }
             // No versions have been installed yet for this alternative, do not add to result
         }
-        return result;
+        result
     }
 }
 
@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::results::{DependencyChange, DiffPackagesResult, DiffedPackage, VersionChange, VersionChangeKind};
+
+impl Packages {
+    /// Loads the Packages indices old_filename and new_filename into two databases of their own (leaving this one alone)
+    /// and returns what changed between them: added and removed packages, version changes classified by the
+    /// component that changed, and dependencies added or removed.
+    pub fn diff_packages(&self, old_filename: &str, new_filename: &str) -> Result<DiffPackagesResult, String> {
+        let mut old = Packages::new();
+        let mut new = Packages::new();
+        old.cache_dir = self.cache_dir.clone();
+        new.cache_dir = self.cache_dir.clone();
+        old.parse_packages(old_filename)?;
+        new.parse_packages(new_filename)?;
+
+        let mut result = DiffPackagesResult {
+            old: String::from(old_filename),
+            new: String::from(new_filename),
+            added: vec![],
+            removed: vec![],
+            version_changes: vec![],
+            dependency_changes: vec![],
+        };
+        let mut names : Vec<&str> = old.available_names().chain(new.available_names()).collect();
+        names.sort_unstable();
+        names.dedup();
+        for package_name in names {
+            match (old.get_available_debver(package_name), new.get_available_debver(package_name)) {
+                (None, None) => unreachable!(),
+                (None, Some(v)) => result.added.push(DiffedPackage { package: String::from(package_name), version: v.to_string() }),
+                (Some(v), None) => result.removed.push(DiffedPackage { package: String::from(package_name), version: v.to_string() }),
+                (Some(old_version), Some(new_version)) => {
+                    if old_version != new_version {
+                        result.version_changes.push(VersionChange {
+                            package: String::from(package_name),
+                            old_version: old_version.to_string(),
+                            new_version: new_version.to_string(),
+                            kind: version_change_kind(old_version, new_version),
+                            downgrade: new_version < old_version,
+                        });
+                    }
+                    let old_deps = old.dependency_strings(package_name);
+                    let new_deps = new.dependency_strings(package_name);
+                    let (old_set, new_set) : (HashSet<&String>, HashSet<&String>) = (old_deps.iter().collect(), new_deps.iter().collect());
+                    let change = DependencyChange {
+                        package: String::from(package_name),
+                        added: new_deps.iter().filter(|d| !old_set.contains(d)).cloned().collect(),
+                        removed: old_deps.iter().filter(|d| !new_set.contains(d)).cloned().collect(),
+                    };
+                    if !change.added.is_empty() || !change.removed.is_empty() {
+                        result.dependency_changes.push(change);
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn available_names(&self) -> impl Iterator<Item = &str> {
+        self.available_debvers.keys().map(move |pn| self.get_package_name(pn))
+    }
+
+    // one string per dependency, e.g. "libc6 (>= 2.34) | libc6.1"
+    fn dependency_strings(&self, package_name: &str) -> Vec<String> {
+        self.dependencies.get(self.get_package_num(package_name)).unwrap().iter().map(|dep| self.dep2str(dep)).collect()
+    }
+}
+
+/// The most significant component that differs between two versions.
+fn version_change_kind(old: &DebianVersionNum, new: &DebianVersionNum) -> VersionChangeKind {
+    if old.epoch() != new.epoch() {
+        VersionChangeKind::Epoch
+    } else if old.upstream() != new.upstream() {
+        VersionChangeKind::Upstream
+    } else {
+        VersionChangeKind::DebianRevision
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::debversion;
+use crate::debversion::DebianVersionNum;
+use crate::Packages;
+use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::interner::PackageId;
+use crate::packages::results::{AlternativeProblem, InstallabilityResult, UninstallablePackage, UninstallableReason};
+
+/// Why one alternative of a dependency can't be used to install a package.
+enum Problem {
+    /// the package isn't in the repository and nothing provides it
+    Missing(PackageId),
+    /// the package is available, but not at a version satisfying the version requirement
+    WrongVersion(PackageId),
+    /// the name is provided by some packages, but none of them satisfy the version requirement
+    NoProvider(PackageId),
+    /// the package (or a provider of it) is available, but not installable itself
+    Uninstallable(PackageId),
+}
+
+/// The dependency (by index into the package's Pre-Depends and Depends) that makes a package uninstallable,
+/// with one Problem per alternative of that dependency.
+struct Uninstallability {
+    dep_index : usize,
+    problems : Vec<Problem>,
+}
+
+impl Packages {
+    /// Determines, for every available package, whether it could be installed from the loaded repository
+    /// (ignoring what's installed locally), and returns the uninstallable ones whose name matches only_regex
+    /// together with the chain of reasons, like edos-debcheck.
+    ///
+    /// Every alternative of every dependency is first resolved to the available packages that could satisfy it
+    /// (the package itself at a suitable version, or a provider); packages with a dependency that can't be resolved
+    /// at all are uninstallable. Then packages with a dependency all of whose candidates are uninstallable are
+    /// marked uninstallable too, until nothing changes. Both steps run in parallel.
+    /// Pre-Depends count as dependencies here; conflicts are not taken into account.
+    pub fn check_installability(&self, only_regex: Option<&str>) -> Result<InstallabilityResult, String> {
+        let only = match only_regex.map(Regex::new) {
+            None => None,
+            Some(Ok(r)) => Some(r),
+            Some(Err(e)) => return Err(format!("bad regex: {}", e))
+        };
+
+        // closures sent to other threads can only borrow the maps, not all of self
+        let (available_debvers, available_provides) = (&self.available_debvers, &self.available_provides);
+        let available : Vec<PackageId> = available_debvers.keys().collect();
+        // the Pre-Depends of each available package followed by its Depends
+        let dependencies : HashMap<PackageId, Vec<&Dependency>> = available.iter().map(|package_num| {
+            let pre_depends = self.available_pre_depends.get(*package_num).into_iter().flatten();
+            (*package_num, pre_depends.chain(self.dependencies.get(*package_num).unwrap()).collect())
+        }).collect();
+        let dependencies = &dependencies;
+
+        // for each package, dependency and alternative, the available packages that could satisfy that alternative
+        let candidates : HashMap<PackageId, Vec<Vec<Vec<PackageId>>>> = available.par_iter().map(|package_num| {
+            let deps = &dependencies[package_num];
+            let dep_candidates = deps.iter().map(|dep| dep.iter().map(|alternative| {
+                let mut c = vec![];
+                if let Some(v) = available_debvers.get(alternative.package_num) {
+                    if version_satisfies(v, alternative) {
+                        c.push(alternative.package_num);
+                    }
+                }
+                for (provider, provided_version) in available_provides.get(alternative.package_num).into_iter().flatten() {
+                    if provided_version_satisfies(provided_version, alternative) {
+                        c.push(*provider);
+                    }
+                }
+                c
+            }).collect()).collect();
+            (*package_num, dep_candidates)
+        }).collect();
+
+        let mut uninstallable : HashMap<PackageId, Uninstallability> = candidates.par_iter().filter_map(|(package_num, dep_candidates)| {
+            let deps = &dependencies[package_num];
+            dep_candidates.iter().position(|alternatives| alternatives.iter().all(|c| c.is_empty())).map(|dep_index| {
+                let problems = deps[dep_index].iter().map(|alternative| {
+                    if available_debvers.contains_key(alternative.package_num) {
+                        Problem::WrongVersion(alternative.package_num)
+                    } else if available_provides.contains_key(alternative.package_num) {
+                        Problem::NoProvider(alternative.package_num)
+                    } else {
+                        Problem::Missing(alternative.package_num)
+                    }
+                }).collect();
+                (*package_num, Uninstallability { dep_index, problems })
+            })
+        }).collect();
+
+        loop {
+            let newly_uninstallable : Vec<(PackageId, Uninstallability)> = candidates.par_iter()
+                .filter(|(package_num, _)| !uninstallable.contains_key(package_num))
+                .filter_map(|(package_num, dep_candidates)| {
+                    let deps = &dependencies[package_num];
+                    dep_candidates.iter().position(|alternatives| alternatives.iter().flatten().all(|c| uninstallable.contains_key(c))).map(|dep_index| {
+                        let problems = deps[dep_index].iter().zip(dep_candidates[dep_index].iter()).map(|(alternative, c)| {
+                            match c.first() {
+                                Some(first) => Problem::Uninstallable(*first),
+                                None if available_debvers.contains_key(alternative.package_num) => Problem::WrongVersion(alternative.package_num),
+                                None if available_provides.contains_key(alternative.package_num) => Problem::NoProvider(alternative.package_num),
+                                None => Problem::Missing(alternative.package_num),
+                            }
+                        }).collect();
+                        (*package_num, Uninstallability { dep_index, problems })
+                    })
+                }).collect();
+            if newly_uninstallable.is_empty() {
+                break;
+            }
+            uninstallable.extend(newly_uninstallable);
+        }
+
+        let mut reported : Vec<&str> = uninstallable.keys().map(|pn| self.get_package_name(*pn))
+            .filter(|name| only.as_ref().is_none_or(|r| r.is_match(name)))
+            .collect();
+        reported.sort_unstable();
+        let checked = match &only {
+            None => available.len(),
+            Some(r) => available.iter().filter(|pn| r.is_match(self.get_package_name(**pn))).count()
+        };
+        Ok(InstallabilityResult {
+            uninstallable: reported.iter().map(|package_name| {
+                let package_num = self.get_package_num(package_name);
+                UninstallablePackage {
+                    package: String::from(*package_name),
+                    version: available_debvers.get(package_num).unwrap().to_string(),
+                    reason: self.uninstallable_reason(package_num, dependencies, &uninstallable, &mut HashSet::new()),
+                }
+            }).collect(),
+            checked,
+        })
+    }
+
+    fn uninstallable_reason(&self, package_num: PackageId, dependencies: &HashMap<PackageId, Vec<&Dependency>>,
+                            uninstallable: &HashMap<PackageId, Uninstallability>, visited: &mut HashSet<PackageId>) -> UninstallableReason {
+        let reason = uninstallable.get(&package_num).unwrap();
+        let dep : &Dependency = dependencies[&package_num][reason.dep_index];
+        let problems = dep.iter().zip(reason.problems.iter()).map(|(alternative, problem)| match problem {
+            Problem::Missing(pn) => AlternativeProblem::Missing { package: String::from(self.get_package_name(*pn)) },
+            Problem::NoProvider(pn) => AlternativeProblem::NoProvider { package: String::from(self.get_package_name(*pn)) },
+            Problem::WrongVersion(pn) => {
+                let (rel, ver) = alternative.rel_version.as_ref().unwrap();
+                AlternativeProblem::WrongVersion {
+                    package: String::from(self.get_package_name(*pn)),
+                    version: self.available_debvers.get(*pn).unwrap().to_string(),
+                    required: format!("{} {}", rel, ver),
+                }
+            }
+            Problem::Uninstallable(pn) => AlternativeProblem::Uninstallable {
+                package: String::from(self.get_package_name(*pn)),
+                version: self.available_debvers.get(*pn).unwrap().to_string(),
+                // each package's reason was recorded before the packages depending on it, so this always terminates,
+                // but there's no need to explain the same package twice
+                reason: if visited.insert(*pn) { Some(Box::new(self.uninstallable_reason(*pn, dependencies, uninstallable, visited))) } else { None },
+            }
+        }).collect();
+        UninstallableReason { dependency: self.dep2str(dep), problems }
+    }
+}
+
+fn version_satisfies(v: &DebianVersionNum, alternative: &RelVersionedPackageNum) -> bool {
+    match &alternative.rel_version {
+        None => true,
+        Some((rel, ver)) => debversion::cmp_debversion_with_op(rel, v, &ver.parse::<DebianVersionNum>().unwrap())
+    }
+}
+
+// An unversioned Provides only satisfies unversioned dependencies
+fn provided_version_satisfies(provided_version: &Option<String>, alternative: &RelVersionedPackageNum) -> bool {
+    match (provided_version, &alternative.rel_version) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some(pv), Some(_)) => version_satisfies(&pv.parse::<DebianVersionNum>().unwrap(), alternative)
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Identifies a package name within one Packages database. Ids are dense, starting at 0 in the order the names
+/// were first seen, so they can index Vecs; an id from one database means nothing in another.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct PackageId(u32);
+
+impl PackageId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Maps package names to PackageIds and back.
+#[derive(Default)]
+pub struct Interner {
+    names : Vec<String>,
+    ids : HashMap<String, PackageId>,
+}
+
+impl Interner {
+    /// Returns the id of name, giving it the next id if it hasn't been seen before.
+    pub fn intern(&mut self, name: &str) -> PackageId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = PackageId(u32::try_from(self.names.len()).expect("too many packages"));
+        self.names.push(String::from(name));
+        self.ids.insert(String::from(name), id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<PackageId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Panics if id comes from another Interner.
+    pub fn name(&self, id: PackageId) -> &str {
+        &self.names[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|n| &n[..])
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = PackageId> {
+        (0..self.names.len() as u32).map(PackageId)
+    }
+}
+
+/// Per-package data stored in a Vec indexed by PackageId, with a HashMap-like interface.
+pub struct PackageMap<T> {
+    values : Vec<Option<T>>,
+    len : usize,
+}
+
+impl<T> Default for PackageMap<T> {
+    fn default() -> Self {
+        PackageMap { values: vec![], len: 0 }
+    }
+}
+
+impl<T> PackageMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: PackageId) -> Option<&T> {
+        self.values.get(id.index()).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, id: PackageId) -> Option<&mut T> {
+        self.values.get_mut(id.index()).and_then(Option::as_mut)
+    }
+
+    pub fn contains_key(&self, id: PackageId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn insert(&mut self, id: PackageId, value: T) -> Option<T> {
+        if self.values.len() <= id.index() {
+            self.values.resize_with(id.index() + 1, || None);
+        }
+        let old = self.values[id.index()].replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// The number of packages with a value.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PackageId, &T)> {
+        self.values.iter().enumerate().filter_map(|(i, v)| v.as_ref().map(|v| (PackageId(i as u32), v)))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.values.iter_mut().flatten()
+    }
+}
+
+impl<T: Default> PackageMap<T> {
+    /// Like HashMap's entry(id).or_default().
+    pub fn get_or_default(&mut self, id: PackageId) -> &mut T {
+        if !self.contains_key(id) {
+            self.insert(id, T::default());
+        }
+        self.get_mut(id).unwrap()
+    }
+}
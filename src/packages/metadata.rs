@@ -0,0 +1,104 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// The Priority field of a package, from most to least important.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Required,
+    Important,
+    Standard,
+    Optional,
+    Extra,
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "required" => Ok(Priority::Required),
+            "important" => Ok(Priority::Important),
+            "standard" => Ok(Priority::Standard),
+            "optional" => Ok(Priority::Optional),
+            "extra" => Ok(Priority::Extra),
+            _ => Err(format!("unknown priority {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Priority::Required => "required",
+            Priority::Important => "important",
+            Priority::Standard => "standard",
+            Priority::Optional => "optional",
+            Priority::Extra => "extra",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Everything in a package's stanza besides the fields that Packages keeps in its own maps
+/// (Package, Version, the checksums and the relationship fields). The common fields are typed;
+/// any others are kept as they appear, in order.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PackageMetadata {
+    /// the one-line synopsis from the Description field
+    pub description : Option<String>,
+    /// the continuation lines of the Description field, without their leading space
+    pub long_description : Option<String>,
+    pub section : Option<String>,
+    pub priority : Option<Priority>,
+    /// in bytes
+    pub size : Option<u64>,
+    /// in KiB
+    pub installed_size : Option<u64>,
+    pub maintainer : Option<String>,
+    pub homepage : Option<String>,
+    pub filename : Option<String>,
+    pub other_fields : Vec<(String, String)>,
+    #[serde(skip)]
+    last_key : String,
+}
+
+impl PackageMetadata {
+    /// Records one "key: value" line of a stanza. Fields with a value that doesn't parse (e.g. an unknown Priority)
+    /// are kept in other_fields.
+    pub fn add_field(&mut self, key: &str, value: &str) {
+        let value = value.trim();
+        self.last_key = String::from(key);
+        match key {
+            "Description" => self.description = Some(String::from(value)),
+            "Section" => self.section = Some(String::from(value)),
+            "Priority" if value.parse::<Priority>().is_ok() => self.priority = value.parse().ok(),
+            "Size" if value.parse::<u64>().is_ok() => self.size = value.parse().ok(),
+            "Installed-Size" if value.parse::<u64>().is_ok() => self.installed_size = value.parse().ok(),
+            "Maintainer" => self.maintainer = Some(String::from(value)),
+            "Homepage" => self.homepage = Some(String::from(value)),
+            "Filename" => self.filename = Some(String::from(value)),
+            _ => self.other_fields.push((String::from(key), String::from(value)))
+        }
+    }
+
+    /// Records a continuation line (one starting with whitespace) of the field most recently added.
+    pub fn continue_field(&mut self, line: &str) {
+        let line = line.strip_prefix(' ').unwrap_or(line);
+        if self.last_key == "Description" {
+            let long_description = self.long_description.get_or_insert_with(String::new);
+            if !long_description.is_empty() {
+                long_description.push('\n');
+            }
+            long_description.push_str(line);
+        } else if let Some((key, value)) = self.other_fields.last_mut() {
+            if *key == self.last_key {
+                // a field may start on the line after its key, like Conffiles
+                value.push('\n');
+                value.push_str(line);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::packages::checksums::ChecksumType;
+use crate::packages::results::ServerStatus;
+
+// a server that failed this many requests in a row is down: it only gets first attempts again after DOWN_TIME
+const DOWN_AFTER_FAILURES : u32 = 3;
+const DOWN_TIME : Duration = Duration::from_secs(30);
+
+/// How one checksum server has been answering.
+#[derive(Clone)]
+struct ServerHealth {
+    addr : String,
+    requests : usize,
+    successes : usize,
+    consecutive_failures : u32,
+    last_failure : Option<Instant>,
+    // of the successful requests
+    total_latency : Duration,
+}
+
+impl ServerHealth {
+    fn new(addr: &str) -> ServerHealth {
+        ServerHealth { addr: String::from(addr), requests: 0, successes: 0, consecutive_failures: 0, last_failure: None, total_latency: Duration::ZERO }
+    }
+
+    fn is_down(&self) -> bool {
+        self.consecutive_failures >= DOWN_AFTER_FAILURES && self.last_failure.is_some_and(|t| t.elapsed() < DOWN_TIME)
+    }
+}
+
+/// The checksum servers, in order of preference, with their health; and the hashes they have returned,
+/// so that servers disagreeing about a package version can be caught.
+#[derive(Clone)]
+pub struct Mirrors {
+    servers : Vec<ServerHealth>,
+    // (package, version, type) -> (server, hash) for every hash received
+    seen_hashes : HashMap<(String, String, ChecksumType), Vec<(String, String)>>,
+}
+
+impl Mirrors {
+    pub fn new(addr: &str) -> Mirrors {
+        Mirrors { servers: vec![ServerHealth::new(addr)], seen_hashes: HashMap::new() }
+    }
+
+    /// Replaces the servers, keeping the health of those that were already there.
+    pub fn set(&mut self, addrs: &[&str]) {
+        let mut old = std::mem::take(&mut self.servers);
+        self.servers = addrs.iter().map(|addr| match old.iter().position(|s| s.addr == *addr) {
+            Some(i) => old.swap_remove(i),
+            None => ServerHealth::new(addr)
+        }).collect();
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn addr(&self, server: usize) -> &str {
+        &self.servers[server].addr
+    }
+
+    pub fn addrs(&self) -> impl Iterator<Item = &str> {
+        self.servers.iter().map(|s| &s.addr[..])
+    }
+
+    /// The server for the first attempt of a request: the first one that isn't down, or if they all are,
+    /// the one that has been down the longest.
+    pub fn first_choice(&self) -> usize {
+        self.servers.iter().position(|s| !s.is_down())
+            .or_else(|| (0..self.servers.len()).min_by_key(|i| self.servers[*i].last_failure))
+            .unwrap()
+    }
+
+    /// The server for the next attempt of a request whose last attempt went to server.
+    pub fn next_after(&self, server: usize) -> usize {
+        (server + 1) % self.servers.len()
+    }
+
+    /// Records how a request to server went: it succeeded if it got a response that isn't worth retrying.
+    pub fn record(&mut self, server: usize, succeeded: bool, latency: Duration) {
+        let health = &mut self.servers[server];
+        health.requests += 1;
+        if succeeded {
+            health.successes += 1;
+            health.consecutive_failures = 0;
+            health.total_latency += latency;
+        } else {
+            health.consecutive_failures += 1;
+            health.last_failure = Some(Instant::now());
+        }
+    }
+
+    /// Records that server returned hash as the algo checksum of package at version, and returns a different hash
+    /// that another server returned for it before, with that server, if there is one.
+    pub fn record_hash(&mut self, package: &str, version: &str, algo: ChecksumType, server: usize, hash: &str) -> Option<(String, String)> {
+        let addr = &self.servers[server].addr;
+        let seen = self.seen_hashes.entry((String::from(package), String::from(version), algo)).or_default();
+        let conflict = seen.iter().find(|(other, other_hash)| other != addr && other_hash != hash).cloned();
+        if !seen.iter().any(|(other, other_hash)| other == addr && other_hash == hash) {
+            seen.push((addr.clone(), String::from(hash)));
+        }
+        conflict
+    }
+
+    pub fn status(&self) -> Vec<ServerStatus> {
+        self.servers.iter().map(|s| ServerStatus {
+            server: s.addr.clone(),
+            up: !s.is_down(),
+            requests: s.requests,
+            successes: s.successes,
+            consecutive_failures: s.consecutive_failures,
+            mean_latency_ms: if s.successes > 0 { Some(s.total_latency.as_micros() as f64 / 1000.0 / s.successes as f64) } else { None },
+            auth: None,
+        }).collect()
+    }
+}
@@ -1,87 +1,132 @@
-use std::collections::HashMap;
-use std::path::Path;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use itertools::Itertools;
 
-use rpkg::debversion;
-use rpkg::debversion::{DebianVersionNum,VersionRelation};
+use crate::debversion;
+use crate::debversion::{DebianVersionNum,VersionRelation};
 
 mod deps_available;
 mod solvers;
 mod parsers;
 mod async_fns;
+mod installability;
+mod search;
+mod cache;
+mod diff;
+mod checksum_server;
+mod mirrors;
+mod verify_cache;
+mod debs;
+pub mod checksums;
+pub mod interner;
+pub mod metadata;
+pub mod results;
 
 use crate::packages::async_fns::AsyncState;
+use crate::packages::checksums::ChecksumType;
+use crate::packages::interner::{Interner, PackageId, PackageMap};
+use crate::packages::metadata::PackageMetadata;
+use crate::packages::results::{DepsResult, HowToInstallResult, InfoResult, PlannedInstall, TransitiveDepSolutionResult};
 
-static PACKAGE_COUNTER: AtomicI32 = AtomicI32::new(0);
+pub use crate::packages::async_fns::{ServerAuth, VerifyPolicy, VerifyProgress};
+pub use crate::packages::cache::default_cache_dir;
+pub use crate::packages::checksum_server::{ChecksumServer, ServeOptions};
+pub use crate::packages::solvers::AlternativePreference;
 
+/// A database of available and installed packages. Each instance has its own package ids,
+/// so several can be loaded side by side.
+///
+/// The loaders and queries return their results rather than printing them; the results in the results module
+/// can be inspected or serialized with serde, and the rpkg binary prints them as text or JSON.
 pub struct Packages {
-    dependencies : HashMap<i32,Vec<Dependency>>,
-    md5sums : HashMap<i32,String>,
-    available_debvers : HashMap<i32,DebianVersionNum>,
-    installed_debvers : HashMap<i32,DebianVersionNum>,
-    package_name_to_num : HashMap<String, i32>,
-    package_num_to_name : HashMap<i32, String>,
+    dependencies : PackageMap<Vec<Dependency>>,
+    // of the available versions
+    checksums : PackageMap<BTreeMap<ChecksumType, String>>,
+    available_debvers : PackageMap<DebianVersionNum>,
+    // keyed by the provided name
+    available_provides : PackageMap<Vec<(PackageId,Option<String>)>>,
+    available_metadata : PackageMap<PackageMetadata>,
+    // only check_installability reads these; the other commands go by dependencies alone
+    available_pre_depends : PackageMap<Vec<Dependency>>,
+    installed_debvers : PackageMap<DebianVersionNum>,
+    installed_stanzas : PackageMap<Vec<String>>,
+    installed_dependencies : PackageMap<Vec<Dependency>>,
+    installed_conflicts : PackageMap<Vec<Dependency>>,
+    installed_provides : PackageMap<Vec<(PackageId,Option<String>)>>,
+    installed_metadata : PackageMap<PackageMetadata>,
+    interner : Interner,
     async_state : AsyncState,
+    cache_dir : Option<PathBuf>,
 }
 
 // Dependency([X, Y, Z]) means X|Y|Z
+#[derive(Clone)]
 pub struct RelVersionedPackageNum {
-    package_num : i32,
+    package_num : PackageId,
     rel_version : Option<(VersionRelation, String)>
 }
 pub type Dependency = Vec<RelVersionedPackageNum>;
 
+// what each package provides, with the provided versions
+type ProvidesByProvider<'a> = PackageMap<Vec<(PackageId, Option<&'a str>)>>;
+
 impl Packages {
     pub fn new() -> Packages {
         Packages { 
-            dependencies : HashMap::new(), 
-            md5sums : HashMap::new(),
-            available_debvers : HashMap::new(),
-            installed_debvers : HashMap::new(),
-            package_name_to_num : HashMap::new(), 
-            package_num_to_name : HashMap::new(),
+            dependencies : PackageMap::new(),
+            checksums : PackageMap::new(),
+            available_debvers : PackageMap::new(),
+            available_provides : PackageMap::new(),
+            available_metadata : PackageMap::new(),
+            available_pre_depends : PackageMap::new(),
+            installed_debvers : PackageMap::new(),
+            installed_stanzas : PackageMap::new(),
+            installed_dependencies : PackageMap::new(),
+            installed_conflicts : PackageMap::new(),
+            installed_provides : PackageMap::new(),
+            installed_metadata : PackageMap::new(),
+            interner : Interner::default(),
             async_state : AsyncState::new(),
+            cache_dir : None,
         }
     }
 
     // next few functions manipulate the list of packages and the name/number interface
     pub fn get_package_names(&self) -> Vec<&str> {
-        self.package_name_to_num.keys().map(|x| &x[..]).collect()
+        self.interner.names().collect()
+    }
+
+    pub fn package_count(&self) -> usize {
+        self.interner.len()
     }
 
-    fn get_package_name(&self, package_num: i32) -> &str {
-        return self.package_num_to_name.get(&package_num).unwrap();
+    fn get_package_name(&self, package_num: PackageId) -> &str {
+        self.interner.name(package_num)
     }
 
     // panics if package_name doesn't already exist
-    fn get_package_num(&self, package_name: &str) -> &i32 {
-        return self.package_name_to_num.get(package_name).unwrap();
+    fn get_package_num(&self, package_name: &str) -> PackageId {
+        self.interner.get(package_name).unwrap()
     }
 
-    // inserts package_name into package_name_to_num if it doesn't already exist
-    fn get_package_num_inserting(&mut self, package_name: &str) -> i32 {
-        if !self.package_name_to_num.contains_key(package_name) {
-            let pnum = PACKAGE_COUNTER.load(Ordering::SeqCst);
-            self.package_name_to_num.insert(String::from(package_name), pnum);
-            self.package_num_to_name.insert(pnum, String::from(package_name));
-            self.dependencies.insert(pnum, vec![]);
-            PACKAGE_COUNTER.fetch_add(1, Ordering::SeqCst);
-            return pnum;
-        } else {
-            return *self.package_name_to_num.get(package_name).unwrap();
+    // interns package_name if it hasn't been seen before
+    fn get_package_num_inserting(&mut self, package_name: &str) -> PackageId {
+        let package_num = self.interner.intern(package_name);
+        if !self.dependencies.contains_key(package_num) {
+            self.dependencies.insert(package_num, vec![]);
         }
+        package_num
     }
 
     pub fn package_exists(&self, package_name: &str) -> bool {
-        return self.package_name_to_num.contains_key(package_name);
+        self.interner.get(package_name).is_some()
     }
 
     // accessor methods for various maps
     pub fn get_available_debver(&self, package_name: &str) -> Option<&DebianVersionNum> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
+        let package_num = self.interner.get(package_name);
+        match package_num {
             None => None,
             Some(x) => match self.available_debvers.get(x) {
                 None => None,
@@ -91,8 +136,8 @@ impl Packages {
     }
 
     pub fn get_installed_debver(&self, package_name: &str) -> Option<&DebianVersionNum> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
+        let package_num = self.interner.get(package_name);
+        match package_num {
             None => None,
             Some(x) => match self.installed_debvers.get(x) {
                 None => None,
@@ -102,118 +147,472 @@ impl Packages {
     }
 
     pub fn get_md5sum(&self, package_name: &str) -> Option<&str> {
-        let package_num = self.package_name_to_num.get(package_name);
-        return match package_num {
-            None => None,
-            Some(x) => match self.md5sums.get(x) {
-                None => None,
-                Some(y) => Some(y)
-            }
-        }
+        self.get_checksum(package_name, ChecksumType::Md5)
+    }
+
+    pub fn get_checksum(&self, package_name: &str, algo: ChecksumType) -> Option<&str> {
+        let package_num = self.interner.get(package_name)?;
+        self.checksums.get(package_num)?.get(&algo).map(|h| &h[..])
+    }
+
+    /// All the checksums known for the available version of package_name, strongest first.
+    pub fn get_checksums(&self, package_name: &str) -> Vec<(ChecksumType, &str)> {
+        let checksums = self.interner.get(package_name).and_then(|package_num| self.checksums.get(package_num));
+        checksums.into_iter().flatten().rev().map(|(algo, hash)| (*algo, &hash[..])).collect()
+    }
+
+    // the metadata of the available version if there is one, otherwise that of the installed version
+    fn metadata(&self, package_num: PackageId) -> Option<&PackageMetadata> {
+        self.available_metadata.get(package_num).or_else(|| self.installed_metadata.get(package_num))
     }
 
     // helper functions; these aren't structs so I can't make them implement Fmt::Display.
-    fn deps2str(&self, deps: &Vec<Dependency>) -> String {
-        return deps.iter().map(|dep| self.dep2str(dep)).format(", ").to_string();
+    fn deps2str(&self, deps: &[Dependency]) -> String {
+        deps.iter().map(|dep| self.dep2str(dep)).format(", ").to_string()
     }
 
     fn dep2str(&self, dep: &Dependency) -> String {
-        return dep.iter().map(|d| {
+        dep.iter().map(|d| {
             let pn = self.get_package_name(d.package_num);
             match &d.rel_version {
                 None => String::from(pn),
-                Some((rel, ver)) => format!("{} ({} {})", pn, rel.to_string(), ver)
+                Some((rel, ver)) => format!("{} ({} {})", pn, rel, ver)
             }
-        }).format(" | ").to_string();
+        }).format(" | ").to_string()
     }
 
-    // output commands
-    pub fn print_deps(&self, package_name: &str) {
-        if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
-        }
-        let deps : &Vec<Dependency> = &*self.dependencies.get(self.get_package_num(package_name)).unwrap();
-        println!("{:?} depends on {:?}", package_name, self.deps2str(deps));
+    fn package_nums2names(&self, package_nums: &[PackageId]) -> Vec<String> {
+        package_nums.iter().map(|pn| String::from(self.get_package_name(*pn))).collect()
     }
 
-    pub fn print_transitive_dep_solution(&self, package_name: &str) {
+    // queries
+    pub fn deps(&self, package_name: &str) -> Result<DepsResult, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        let dep_solution : Vec<i32> = self.transitive_dep_solution(package_name);
-        println!("{:?} transitive dependency solution: {:?}", package_name, dep_solution.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        let deps : &Vec<Dependency> = self.dependencies.get(self.get_package_num(package_name)).unwrap();
+        Ok(DepsResult {
+            package: String::from(package_name),
+            depends: deps.iter().map(|dep| self.dep2str(dep)).collect(),
+        })
     }
 
-    pub fn print_how_to_install(&self, package_name: &str) {
+    pub fn transitive_dep_solution(&self, package_name: &str) -> Result<TransitiveDepSolutionResult, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package {}:", package_name);
-        let pkgs_to_install:Vec<i32> = self.compute_how_to_install(package_name);
-        println!("{:?} to install: {:?}", package_name, pkgs_to_install.iter().map(|dep| self.get_package_name(*dep)).format(", ").to_string());
+        let dep_solution : Vec<PackageId> = self.compute_transitive_dep_solution(package_name);
+        Ok(TransitiveDepSolutionResult {
+            package: String::from(package_name),
+            solution: self.package_nums2names(&dep_solution),
+        })
     }
 
-    pub fn print_info(&self, package_name: &str) {
+    /// The packages needed to install package_name, with what each costs in download size and disk space;
+    /// preference says how to choose among alternatives that aren't installed.
+    pub fn how_to_install(&self, package_name: &str, preference: AlternativePreference) -> Result<HowToInstallResult, String> {
         if !self.package_exists(package_name) {
-            println!("no such package {}", package_name);
-            return;
+            return Err(format!("no such package {}", package_name));
         }
-        println!("Package: {}", package_name);
-        let a = self.get_available_debver(package_name);
-        let i = self.get_installed_debver(package_name);
-        match a {
-            None => (),
-            Some(a) => {
-                println!("Version: {}", a.to_string());
-                println!("MD5Sum: {}", self.get_md5sum(package_name).unwrap().to_string());
-                println!("Depends: {}", self.deps2str(&*self.dependencies.get(self.get_package_num(package_name)).unwrap()));
+        let pkgs_to_install:Vec<PackageId> = self.compute_how_to_install(package_name, preference);
+        // the plan also includes the package itself, unless it's already installed at the available version
+        let package_num = self.get_package_num(package_name);
+        let up_to_date = matches!((self.available_debvers.get(package_num), self.installed_debvers.get(package_num)), (Some(a), Some(i)) if a <= i);
+        let plan : Vec<PlannedInstall> = std::iter::once(package_num).filter(|_| !up_to_date)
+            .chain(pkgs_to_install.iter().copied())
+            .map(|pn| self.planned_install(pn))
+            .collect();
+        Ok(HowToInstallResult {
+            package: String::from(package_name),
+            to_install: self.package_nums2names(&pkgs_to_install),
+            total_download_size: plan.iter().filter_map(|p| p.download_size).sum(),
+            total_installed_size_delta: plan.iter().filter_map(|p| p.installed_size_delta).sum(),
+            unknown_sizes: plan.iter().filter(|p| p.download_size.is_none() || p.installed_size_delta.is_none()).count(),
+            plan,
+        })
+    }
+
+    /// Installs package_name as how_to_install plans it, but only in the maps of installed packages: the planned
+    /// packages are recorded as installed at their available versions, with the available dependencies, conflicts,
+    /// provides and metadata, so that check, how_to_install and the rest see them. save_installed writes stanzas for
+    /// them synthesized from the available metadata. Returns the plan.
+    pub fn simulate_install(&mut self, package_name: &str, preference: AlternativePreference) -> Result<HowToInstallResult, String> {
+        let plan = self.how_to_install(package_name, preference)?;
+        for planned in &plan.plan {
+            let package_num = self.get_package_num(&planned.package);
+            // e.g. a name that nothing provides
+            if self.available_debvers.contains_key(package_num) {
+                self.install_available(package_num);
             }
         }
-        match i {
-            None => (),
-            Some(i) => { println!("Installed-Version: {}", i.to_string()) }
+        Ok(plan)
+    }
+
+    /// What installing the available version of package_num costs; for an upgrade, the disk space delta
+    /// takes away the Installed-Size of the installed version.
+    fn planned_install(&self, package_num: PackageId) -> PlannedInstall {
+        let available = self.available_metadata.get(package_num);
+        let installed_version = self.installed_debvers.get(package_num);
+        let new_size = available.and_then(|m| m.installed_size);
+        let old_size = match installed_version {
+            None => Some(0),
+            Some(_) => self.installed_metadata.get(package_num).and_then(|m| m.installed_size)
+        };
+        PlannedInstall {
+            package: String::from(self.get_package_name(package_num)),
+            version: self.available_debvers.get(package_num).map(|v| v.to_string()),
+            installed_version: installed_version.map(|v| v.to_string()),
+            download_size: available.and_then(|m| m.size),
+            // Installed-Size is in KiB
+            installed_size_delta: match (new_size, old_size) {
+                (Some(new), Some(old)) => Some((new as i64 - old as i64) * 1024),
+                _ => None
+            },
         }
-        match (a, i) {
-            (Some(aa), Some(ii)) =>
-                { println!("Newer-Available: {:?}", aa > ii); }
-            _ => ()
+    }
+
+    /// Everything known about package_name, integrating available and installed information.
+    pub fn info(&self, package_name: &str) -> Result<InfoResult, String> {
+        if !self.package_exists(package_name) {
+            return Err(format!("no such package {}", package_name));
         }
+        let a = self.get_available_debver(package_name);
+        let i = self.get_installed_debver(package_name);
+        let package_num = self.get_package_num(package_name);
+        Ok(InfoResult {
+            package: String::from(package_name),
+            version: a.map(|a| a.to_string()),
+            md5sum: a.and(self.get_md5sum(package_name)).map(String::from),
+            sha256: a.and(self.get_checksum(package_name, ChecksumType::Sha256)).map(String::from),
+            sha512: a.and(self.get_checksum(package_name, ChecksumType::Sha512)).map(String::from),
+            depends: a.map(|_| self.dependencies.get(package_num).unwrap().iter().map(|dep| self.dep2str(dep)).collect()),
+            installed_version: i.map(|i| i.to_string()),
+            newer_available: match (a, i) {
+                (Some(aa), Some(ii)) => Some(aa > ii),
+                _ => None
+            },
+            metadata: self.metadata(package_num).cloned(),
+        })
     }
 
     // generate output for package-verifier
-    pub fn output_md5s(&self, fname: &str) {
+    pub fn output_md5s(&self, fname: &str) -> Result<(), String> {
+        self.output_checksums(fname, ChecksumType::Md5).map(|_| ())
+    }
+
+    /// Writes the available packages whose algo checksum is known to fname as a name,version,hash CSV file
+    /// (name,version,sha256 and so on for the SHA checksums), which parse_csv reads back.
+    /// Returns the number of packages written.
+    pub fn output_checksums(&self, fname: &str, algo: ChecksumType) -> Result<usize, String> {
         let path = Path::new(fname);
-        let mut md5s : String = "name,version,hash\n".to_owned();
+        let column = if algo == ChecksumType::Md5 { String::from("hash") } else { algo.to_string() };
+        let mut checksums : String = format!("name,version,{}\n", column);
+        let mut written = 0;
         for pn in self.get_package_names() {
-            match (self.get_available_debver(pn), self.get_md5sum(pn)) {
-                (Some(v), Some(m)) => {
-                    let row = format!("{},{},{}\n",pn,v.to_string(),m);
-                    md5s.push_str(&row)
+            if let (Some(v), Some(h)) = (self.get_available_debver(pn), self.get_checksum(pn, algo)) {
+                let row = format!("{},{},{}\n",pn,v,h);
+                checksums.push_str(&row);
+                written += 1;
+            }
+        }
+        write_file(path, checksums)?;
+        Ok(written)
+    }
+
+    /// Writes the installed packages to fname in dpkg status format. Packages whose installed version is still
+    /// the one read by parse_installed are written out with their original stanzas; anything else (e.g. a simulated
+    /// install) gets a stanza synthesized from the available metadata. Returns the number of installed packages.
+    pub fn save_installed(&self, fname: &str) -> Result<usize, String> {
+        let path = Path::new(fname);
+        let mut status = String::new();
+        let mut package_names = self.get_package_names();
+        package_names.sort_unstable();
+        // only built if some stanza has to be synthesized
+        let mut provides_by_provider = None;
+        for pn in package_names {
+            let package_num = self.get_package_num(pn);
+            let installed_version = self.installed_debvers.get(package_num).map(|v| v.to_string());
+            let mut found_installed_stanza = false;
+            for stanza in self.installed_stanzas.get(package_num).into_iter().flatten() {
+                let is_installed = stanza.lines().any(|l| l.starts_with("Status: ") && l.ends_with(" installed"));
+                let version = stanza.lines().find_map(|l| l.strip_prefix("Version: ")).map(|v| v.trim().to_string());
+                // stanzas for packages that aren't installed (e.g. config-files) are always kept as they are
+                if is_installed && version != installed_version {
+                    continue;
                 }
-                (_, _) => ()
+                found_installed_stanza |= is_installed;
+                status.push_str(stanza);
+                status.push('\n');
+            }
+            if let (Some(installed_version), false) = (&installed_version, found_installed_stanza) {
+                let provides = provides_by_provider.get_or_insert_with(|| invert_provides(&self.available_provides));
+                status.push_str(&self.synthesize_installed_stanza(pn, installed_version, provides));
+                status.push('\n');
+            }
+        }
+        write_file(path, status)?;
+        Ok(self.installed_debvers.len())
+    }
+
+    // Writes the fields of a dpkg status stanza in the order dpkg uses, taking everything but the version from the
+    // available package.
+    fn synthesize_installed_stanza(&self, package_name: &str, version: &str, provides_by_provider: &ProvidesByProvider) -> String {
+        let package_num = self.get_package_num(package_name);
+        let metadata = self.metadata(package_num);
+        let other_field = |key: &str| metadata.and_then(|m| m.other_fields.iter().find(|(k, _)| k == key)).map(|(_, v)| &v[..]);
+        let mut stanza = format!("Package: {}\nStatus: install ok installed\n", package_name);
+        let mut push_field = |key: &str, value: &str| {
+            // continuation lines start with a space
+            stanza.push_str(&format!("{}: {}\n", key, value.replace('\n', "\n ")));
+        };
+        if let Some(priority) = metadata.and_then(|m| m.priority) {
+            push_field("Priority", &priority.to_string());
+        }
+        if let Some(section) = metadata.and_then(|m| m.section.as_ref()) {
+            push_field("Section", section);
+        }
+        if let Some(installed_size) = metadata.and_then(|m| m.installed_size) {
+            push_field("Installed-Size", &installed_size.to_string());
+        }
+        if let Some(maintainer) = metadata.and_then(|m| m.maintainer.as_ref()) {
+            push_field("Maintainer", maintainer);
+        }
+        for key in &["Architecture", "Multi-Arch", "Source"] {
+            if let Some(value) = other_field(key) {
+                push_field(key, value);
+            }
+        }
+        push_field("Version", version);
+        if let Some(value) = other_field("Replaces") {
+            push_field("Replaces", value);
+        }
+        if let Some(provided) = provides_by_provider.get(package_num) {
+            let provided = provided.iter().map(|(provided_num, provided_version)| match provided_version {
+                None => String::from(self.get_package_name(*provided_num)),
+                Some(v) => format!("{} (= {})", self.get_package_name(*provided_num), v)
+            }).format(", ").to_string();
+            push_field("Provides", &provided);
+        }
+        let deps = self.dependencies.get(package_num).unwrap();
+        if !deps.is_empty() {
+            push_field("Depends", &self.deps2str(deps));
+        }
+        for key in &["Pre-Depends", "Recommends", "Suggests", "Breaks", "Conflicts", "Enhances"] {
+            if let Some(value) = other_field(key) {
+                push_field(key, value);
             }
         }
-        std::fs::write(path, md5s).unwrap();
+        if let Some(description) = metadata.and_then(|m| m.description.as_ref()) {
+            match metadata.and_then(|m| m.long_description.as_ref()) {
+                None => push_field("Description", description),
+                Some(long_description) => push_field("Description", &format!("{}\n{}", description, long_description))
+            }
+        }
+        if let Some(homepage) = metadata.and_then(|m| m.homepage.as_ref()) {
+            push_field("Homepage", homepage);
+        }
+        stanza
     }
 
-    // provided parse function to let students do the async io part independently
-    pub fn parse_csv(&mut self, filename: &str) {
-        let mut rdr = csv::Reader::from_path(filename).unwrap();
-        for line in rdr.records() {
-            let line = line.unwrap();
-            let package_name = String::from(line.get(0).unwrap());
-            let debver = String::from(line.get(1).unwrap()).parse::<debversion::DebianVersionNum>().unwrap();
-            let md5sum = String::from(line.get(2).unwrap());
+    // provided parse function to let students do the async io part independently;
+    // the columns after name and version are checksums, named by type, where hash means MD5.
+    // returns the number of available packages
+    pub fn parse_csv(&mut self, filename: &str) -> Result<usize, String> {
+        let mut rdr = match csv::Reader::from_path(filename) {
+            Ok(rdr) => rdr,
+            Err(e) => return Err(format!("couldn't open {}: {}", filename, e))
+        };
+        let algos = match rdr.headers() {
+            Ok(headers) => headers.iter().skip(2).map(|h| match h {
+                "hash" => Ok(ChecksumType::Md5),
+                _ => h.parse::<ChecksumType>()
+            }).collect::<Result<Vec<_>, _>>().map_err(|e| format!("{}: {}", filename, e))?,
+            Err(e) => return Err(format!("couldn't read {}: {}", filename, e))
+        };
+        for (row, line) in rdr.records().enumerate() {
+            // counting the header, so that this is the line number for rows without quoted newlines
+            let row = row + 2;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Err(format!("{}: couldn't read row {}: {}", filename, row, e))
+            };
+            let (package_name, version) = match (line.get(0), line.get(1)) {
+                (Some(package_name), Some(version)) => (String::from(package_name), version),
+                _ => return Err(format!("{}: row {} has no name and version", filename, row))
+            };
+            // parsing a version never fails
+            let debver = version.parse::<debversion::DebianVersionNum>().unwrap();
 
             let package_num = self.get_package_num_inserting(&package_name);
             self.available_debvers.insert(package_num, debver);
-            self.md5sums.insert(package_num, md5sum);
+            let checksums = algos.iter().zip(line.iter().skip(2)).filter(|(_, hash)| !hash.is_empty())
+                .map(|(algo, hash)| (*algo, String::from(hash))).collect();
+            self.checksums.insert(package_num, checksums);
 
         }
 
-        println!("Packages available: {}", self.available_debvers.keys().len());
+        Ok(self.available_debvers.len())
+    }
+}
+
+impl Default for Packages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What each package provides, from a map of what is provided to its providers (available_provides or installed_provides).
+fn invert_provides(provides: &PackageMap<Vec<(PackageId, Option<String>)>>) -> ProvidesByProvider<'_> {
+    let mut provides_by_provider = ProvidesByProvider::new();
+    for (provided_num, providers) in provides.iter() {
+        for (provider, provided_version) in providers {
+            provides_by_provider.get_or_default(*provider).push((provided_num, provided_version.as_deref()));
+        }
+    }
+    provides_by_provider
+}
+
+fn write_file(path: &Path, contents: String) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_can_move_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Packages>();
+    }
+
+    /// A directory of a test's own under the temporary directory, removed when dropped, so also when the test fails.
+    pub(super) struct TestDir {
+        path : PathBuf,
+    }
+
+    impl TestDir {
+        pub(super) fn new(test: &str) -> TestDir {
+            let path = std::env::temp_dir().join(format!("rpkg-test-{}-{}", std::process::id(), test));
+            std::fs::create_dir_all(&path).unwrap();
+            TestDir { path }
+        }
+
+        pub(super) fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Writes contents to the file name in the directory, and returns its path as the loaders take it.
+        pub(super) fn write(&self, name: &str, contents: &str) -> String {
+            let path = self.path.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn available_pre_depends_only_count_for_installability() {
+        let dir = TestDir::new("pre-depends");
+        let packages_file = dir.write("Packages", "\
+Package: a
+Version: 1
+Pre-Depends: missing
+Depends: c
+
+Package: b
+Version: 1
+Depends: a
+
+Package: c
+Version: 1
+");
+        let mut packages = Packages::new();
+        packages.parse_packages(&packages_file).unwrap();
+        assert_eq!(packages.deps("a").unwrap().depends, vec!["c"]);
+        let uninstallable = packages.check_installability(None).unwrap().uninstallable;
+        assert_eq!(uninstallable.iter().map(|u| u.package.as_str()).collect_vec(), vec!["a", "b"]);
+        assert_eq!(uninstallable[0].reason.dependency, "missing");
+    }
+
+    #[test]
+    fn simulated_installs_are_saved_with_synthesized_stanzas() {
+        let dir = TestDir::new("simulate");
+        let packages_file = dir.write("Packages", "\
+Package: foo
+Version: 1.2-3
+Installed-Size: 120
+Maintainer: Jane Doe <jane@example.org>
+Architecture: amd64
+Provides: foo-api (= 1.2), bar
+Pre-Depends: libc6 (>= 2.34)
+Depends: baz | qux (>= 2)
+Conflicts: oldfoo
+Size: 4096
+Filename: pool/main/f/foo/foo_1.2-3_amd64.deb
+SHA256: 0000000000000000000000000000000000000000000000000000000000000000
+Description: does foo
+ Foo does all the
+ .
+ foo things.
+
+Package: baz
+Version: 1
+
+Package: qux
+Version: 1
+
+");
+        let installed_file = dir.write("status", "\
+Package: keep
+Status: install ok installed
+Version: 3
+
+Package: foo
+Status: install ok installed
+Version: 1.0
+");
+        let mut packages = Packages::new();
+        packages.parse_packages(&packages_file).unwrap();
+        packages.parse_installed(&installed_file).unwrap();
+        let plan = packages.simulate_install("foo", AlternativePreference::HighestVersion).unwrap();
+        assert_eq!(plan.plan.iter().map(|p| p.package.as_str()).collect_vec(), vec!["foo", "baz"]);
+        assert_eq!(packages.deps_available("foo").unwrap().dependencies[0].satisfied_by.as_deref(), Some("baz"));
+        // libc6 isn't available, and installed packages have their Pre-Depends checked too
+        assert_eq!(packages.check().broken_packages, 1);
+
+        let status_file = dir.path().join("saved-status");
+        packages.save_installed(status_file.to_str().unwrap()).unwrap();
+        let status = std::fs::read_to_string(&status_file).unwrap();
+        assert_eq!(status, "\
+Package: baz
+Status: install ok installed
+Version: 1
+
+Package: foo
+Status: install ok installed
+Installed-Size: 120
+Maintainer: Jane Doe <jane@example.org>
+Architecture: amd64
+Version: 1.2-3
+Provides: foo-api (= 1.2), bar
+Depends: baz | qux (>= 2)
+Pre-Depends: libc6 (>= 2.34)
+Conflicts: oldfoo
+Description: does foo
+ Foo does all the
+ .
+ foo things.
+
+Package: keep
+Status: install ok installed
+Version: 3
+
+");
     }
 }
@@ -2,126 +2,343 @@ use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 
-use regex::{Captures, Match, Regex};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use crate::Packages;
 use crate::packages::{Dependency, RelVersionedPackageNum};
+use crate::packages::checksums::ChecksumType;
+use crate::packages::interner::PackageId;
+use crate::packages::metadata::PackageMetadata;
 
-use rpkg::debversion;
-use rpkg::debversion::VersionRelation;
+use crate::debversion;
 
-const KEYVAL_REGEX : &str = r"(?P<key>(\w|-)+): (?P<value>.+)";
+const KEYVAL_REGEX : &str = r"^(?P<key>(\w|-)+): (?P<value>.+)";
+// fields of the installed stanza that are recorded in maps of their own rather than in installed_metadata
+const METADATA_EXCLUDED_KEYS : &[&str] = &["Package", "Status", "Version", "Provides", "Depends", "Pre-Depends", "Conflicts", "Breaks"];
 const PKGNAME_AND_VERSION_REGEX : &str = r"(?P<pkg>(\w|\.|\+|-)+)( \((?P<op>(<|=|>)(<|=|>)?) (?P<ver>.*)\))?";
 
+// Files are parsed in two steps: first into stanzas that refer to packages by name, then those are loaded into
+// the maps, numbering packages as they go. The stanzas don't depend on what's already loaded, so they can be cached.
+
+/// One alternative of a dependency as it appears in the file, e.g. "b (>= 2)".
+#[derive(Serialize, Deserialize)]
+pub struct ParsedAlternative {
+    package : String,
+    rel_version : Option<(String, String)>,
+}
+
+/// The fields of a Packages stanza that parse_packages records.
+#[derive(Serialize, Deserialize)]
+pub struct AvailableStanza {
+    package : String,
+    version : Option<String>,
+    checksums : Vec<(ChecksumType, String)>,
+    depends : Option<Vec<Vec<ParsedAlternative>>>,
+    pre_depends : Option<Vec<Vec<ParsedAlternative>>>,
+    provides : Vec<ParsedAlternative>,
+    metadata : PackageMetadata,
+}
+
+/// A stanza of a status file; only installed packages have their fields recorded.
+#[derive(Serialize, Deserialize)]
+pub struct InstalledStanza {
+    package : String,
+    text : String,
+    installed : Option<InstalledFields>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct InstalledFields {
+    version : Option<String>,
+    provides : Vec<ParsedAlternative>,
+    depends : Option<Vec<Vec<ParsedAlternative>>>,
+    conflicts : Option<Vec<Vec<ParsedAlternative>>>,
+    metadata : PackageMetadata,
+}
+
 impl Packages {
     /// Loads packages and version numbers from a file, calling get_package_num_inserting on the package name
     /// and inserting the appropriate value into the installed_debvers map with the parsed version number.
-    pub fn parse_installed(&mut self, filename: &str) {
-        let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                    // do something with ip
-                    if kv_regexp.is_match(&ip) {
-                        let caps = kv_regexp.captures(&ip).unwrap();
-                        let key = caps.name("key").unwrap().as_str();
-                        let value = caps.name("value").unwrap().as_str();
-                        //println!("{}: {}", key, value);
-
-                        if key == "Package" {
-                            current_package_num = self.get_package_num_inserting(&value);
-                            //println!("Package: {}, package num: {}", value, current_package_num);
-                        }
-
-                        if key == "Version" {
-                            let debver = value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                            self.installed_debvers.insert(current_package_num, debver); // Assume we always receive Package line before the Version line
-                        }
-                    }
-                }
+    /// Only packages whose Status says they are installed are recorded; packages left with just their config files are skipped.
+    /// The raw text of each stanza is also kept in installed_stanzas so that save_installed can write it back out,
+    /// the Provides go into installed_provides, and the installed package's own Depends/Pre-Depends and
+    /// Conflicts/Breaks go into installed_dependencies and installed_conflicts for the check command.
+    /// The remaining fields of installed packages go into installed_metadata.
+    /// The parsed file is cached, and the cache is used instead of parsing again as long as the file is unchanged.
+    /// Returns the number of installed packages.
+    pub fn parse_installed(&mut self, filename: &str) -> Result<usize, String> {
+        let stanzas = match self.load_cached(filename, "installed") {
+            Some(stanzas) => stanzas,
+            None => match read_installed_stanzas(filename) {
+                Ok(stanzas) => { self.store_cached(filename, "installed", &stanzas); stanzas }
+                Err(_) => return Err(format!("couldn't open {}", filename))
             }
-            // for (key, value) in &self.installed_debvers {
-            //     println!("{}: {}", key, value);
-            // }
+        };
+        for stanza in stanzas {
+            self.load_installed_stanza(stanza);
         }
-        println!("Packages installed: {}", self.installed_debvers.keys().len());
+        Ok(self.installed_debvers.len())
     }
 
-    /// Loads packages, version numbers, dependencies, and md5sums from a file, calling get_package_num_inserting on the package name
-    /// and inserting the appropriate values into the dependencies, md5sum, and available_debvers maps.
-    pub fn parse_packages(&mut self, filename: &str) {
-        let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
+    fn load_installed_stanza(&mut self, stanza: InstalledStanza) {
+        let package_num = self.get_package_num_inserting(&stanza.package);
+        self.installed_stanzas.get_or_default(package_num).push(stanza.text);
+        let fields = match stanza.installed {
+            None => return,
+            Some(fields) => fields
+        };
+        if let Some(version) = fields.version {
+            let debver = version.trim().parse::<debversion::DebianVersionNum>().unwrap();
+            self.installed_debvers.insert(package_num, debver);
+        }
+        for provided in fields.provides {
+            let provided_num = self.get_package_num_inserting(&provided.package);
+            let version = provided.rel_version.map(|(_, ver)| ver);
+            self.installed_provides.get_or_default(provided_num).push((package_num, version));
+        }
+        if let Some(depends) = fields.depends {
+            let mut dependencies_vec = self.number_dependency_list(depends);
+            self.installed_dependencies.get_or_default(package_num).append(&mut dependencies_vec);
+        }
+        if let Some(conflicts) = fields.conflicts {
+            let mut conflicts_vec = self.number_dependency_list(conflicts);
+            self.installed_conflicts.get_or_default(package_num).append(&mut conflicts_vec);
+        }
+        self.installed_metadata.insert(package_num, fields.metadata);
+    }
+
+    /// Records the available version of package_num as installed, replacing what was recorded for the installed
+    /// version, as load_installed_stanza does for a stanza; installed_stanzas is left alone.
+    pub(super) fn install_available(&mut self, package_num: PackageId) {
         let pkgver_regexp = Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap();
+        self.installed_debvers.insert(package_num, self.available_debvers.get(package_num).unwrap().clone());
+        for providers in self.installed_provides.values_mut() {
+            providers.retain(|(provider, _)| *provider != package_num);
+        }
+        let provided : Vec<(PackageId, Option<String>)> = self.available_provides.iter()
+            .flat_map(|(provided_num, providers)| providers.iter().filter(|(provider, _)| *provider == package_num)
+                .map(move |(_, version)| (provided_num, version.clone())))
+            .collect();
+        for (provided_num, version) in provided {
+            self.installed_provides.get_or_default(provided_num).push((package_num, version));
+        }
+        // like an installed stanza's, the dependencies include the Pre-Depends
+        let dependencies = self.available_pre_depends.get(package_num).into_iter().flatten()
+            .chain(self.dependencies.get(package_num).unwrap()).cloned().collect();
+        self.installed_dependencies.insert(package_num, dependencies);
+        let mut metadata = self.available_metadata.get(package_num).cloned().unwrap_or_default();
+        let conflicts = metadata.other_fields.iter().filter(|(key, _)| key == "Conflicts" || key == "Breaks")
+            .flat_map(|(_, value)| parse_dependency_list(value, &pkgver_regexp)).collect();
+        let conflicts = self.number_dependency_list(conflicts);
+        self.installed_conflicts.insert(package_num, conflicts);
+        metadata.other_fields.retain(|(key, _)| !METADATA_EXCLUDED_KEYS.contains(&&key[..]));
+        self.installed_metadata.insert(package_num, metadata);
+    }
+
+    /// Turns parsed dependencies into Dependencies, calling get_package_num_inserting on every package name mentioned.
+    fn number_dependency_list(&mut self, dependencies: Vec<Vec<ParsedAlternative>>) -> Vec<Dependency> {
+        dependencies.into_iter().map(|alternatives| alternatives.into_iter().map(|alternative| {
+            RelVersionedPackageNum {
+                package_num: self.get_package_num_inserting(&alternative.package),
+                rel_version: alternative.rel_version.map(|(op, ver)| (op.parse::<debversion::VersionRelation>().unwrap(), ver))
+            }
+        }).collect()).collect()
+    }
+
+    /// Loads packages, version numbers, dependencies, provides, and checksums (MD5sum, SHA256, SHA512) from a file, calling get_package_num_inserting on the package name
+    /// and inserting the appropriate values into the dependencies, checksums, available_provides, and available_debvers maps.
+    /// Pre-Depends go into available_pre_depends, which only check_installability reads, and stay in available_metadata as written.
+    /// All the other fields (Description, Section, Size, ...) go into available_metadata.
+    /// Like parse_installed, this uses the cache when the file hasn't changed since it was last parsed.
+    /// Returns the number of available packages.
+    pub fn parse_packages(&mut self, filename: &str) -> Result<usize, String> {
+        let stanzas = match self.load_cached(filename, "packages") {
+            Some(stanzas) => stanzas,
+            None => match read_available_stanzas(filename) {
+                Ok(stanzas) => { self.store_cached(filename, "packages", &stanzas); stanzas }
+                Err(_) => return Err(format!("couldn't open {}", filename))
+            }
+        };
+        for stanza in stanzas {
+            self.load_available_stanza(stanza);
+        }
+        Ok(self.available_debvers.len())
+    }
+
+    fn load_available_stanza(&mut self, stanza: AvailableStanza) {
+        let package_num = self.get_package_num_inserting(&stanza.package);
+        if let Some(version) = stanza.version {
+            let debver = version.trim().parse::<debversion::DebianVersionNum>().unwrap();
+            self.available_debvers.insert(package_num, debver);
+        }
+        if !stanza.checksums.is_empty() {
+            self.checksums.insert(package_num, stanza.checksums.into_iter().collect());
+        }
+        if let Some(depends) = stanza.depends {
+            let dependencies_vec = self.number_dependency_list(depends);
+            self.dependencies.insert(package_num, dependencies_vec);
+        }
+        if let Some(pre_depends) = stanza.pre_depends {
+            let pre_depends_vec = self.number_dependency_list(pre_depends);
+            self.available_pre_depends.insert(package_num, pre_depends_vec);
+        }
+        for provided in stanza.provides {
+            let provided_num = self.get_package_num_inserting(&provided.package);
+            let version = provided.rel_version.map(|(_, ver)| ver);
+            self.available_provides.get_or_default(provided_num).push((package_num, version));
+        }
+        self.available_metadata.insert(package_num, stanza.metadata);
+    }
+}
+
+/// Reads the stanzas of a dpkg status file.
+fn read_installed_stanzas(filename: &str) -> io::Result<Vec<InstalledStanza>> {
+    let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
+    let pkgver_regexp = Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap();
+    let mut stanzas = vec![];
+    let mut current = InstalledStanza { package: String::new(), text: String::new(), installed: None };
+    for ip in read_lines(filename)?.map_while(Result::ok) {
+        if ip.trim().is_empty() {
+            if !current.text.is_empty() {
+                let next = InstalledStanza { package: current.package.clone(), text: String::new(), installed: None };
+                stanzas.push(std::mem::replace(&mut current, next));
+            }
+            continue;
+        }
+        current.text.push_str(&ip);
+        current.text.push('\n');
+
+        if ip.starts_with(char::is_whitespace) {
+            if let Some(fields) = &mut current.installed {
+                fields.metadata.continue_field(&ip);
+            }
+            continue;
+        }
+
+        // do something with ip
+        if let Some(caps) = kv_regexp.captures(&ip) {
+            let key = caps.name("key").unwrap().as_str();
+            let value = caps.name("value").unwrap().as_str();
+
+            if key == "Package" {
+                current.package = String::from(value);
+                current.installed = None;
+            }
+
+            // e.g. "install ok installed" or "hold ok installed", but not "deinstall ok config-files"
+            if key == "Status" && value.ends_with(" installed") {
+                current.installed = Some(InstalledFields::default());
+            }
+
+            // packages whose status isn't installed keep their stanza, but nothing else is recorded for them
+            let fields = match &mut current.installed {
+                None => continue,
+                Some(fields) => fields
+            };
+            match key {
+                // Assume we always receive Package and Status lines before the Version line
+                "Version" => fields.version = Some(String::from(value)),
+                "Provides" => fields.provides.extend(parse_dependency_list(value, &pkgver_regexp).into_iter().flatten()),
+                "Depends" | "Pre-Depends" => fields.depends.get_or_insert_with(Vec::new).append(&mut parse_dependency_list(value, &pkgver_regexp)),
+                "Conflicts" | "Breaks" => fields.conflicts.get_or_insert_with(Vec::new).append(&mut parse_dependency_list(value, &pkgver_regexp)),
+                _ => {}
+            }
+            if !METADATA_EXCLUDED_KEYS.contains(&key) {
+                fields.metadata.add_field(key, value);
+            }
+        } else if let (Some(key), Some(fields)) = (ip.strip_suffix(':'), &mut current.installed) {
+            // a field whose value is all on continuation lines, e.g. Conffiles
+            fields.metadata.add_field(key, "");
+        }
+    }
+    if !current.text.is_empty() {
+        stanzas.push(current);
+    }
+    Ok(stanzas)
+}
+
+/// Reads the stanzas of a Packages index; a stanza starts at each Package line.
+fn read_available_stanzas(filename: &str) -> io::Result<Vec<AvailableStanza>> {
+    let kv_regexp = Regex::new(KEYVAL_REGEX).unwrap();
+    let pkgver_regexp = Regex::new(PKGNAME_AND_VERSION_REGEX).unwrap();
+    let mut stanzas : Vec<AvailableStanza> = vec![];
+    for ip in read_lines(filename)?.map_while(Result::ok) {
+        if ip.starts_with(char::is_whitespace) {
+            if let Some(current) = stanzas.last_mut() {
+                current.metadata.continue_field(&ip);
+            }
+            continue;
+        }
+        // do more things with ip
+        let caps = match kv_regexp.captures(&ip) {
+            None => continue,
+            Some(caps) => caps
+        };
+        let (key, value) = (
+            caps.name("key").unwrap().as_str(),
+            caps.name("value").unwrap().as_str()
+        );
+        if key == "Package" {
+            stanzas.push(AvailableStanza {
+                package: String::from(value), version: None, checksums: vec![], depends: None, pre_depends: None, provides: vec![], metadata: PackageMetadata::default()
+            });
+            continue;
+        }
+        // Assume we always receive Package line before the other lines
+        let current = match stanzas.last_mut() {
+            None => continue,
+            Some(current) => current
+        };
+        match key {
+            "Version" => current.version = Some(String::from(value)),
+            "Depends" => current.depends = Some(parse_dependency_list(value, &pkgver_regexp)),
+            "Pre-Depends" => {
+                current.pre_depends = Some(parse_dependency_list(value, &pkgver_regexp));
+                // kept as written too, for info and save_installed
+                current.metadata.add_field(key, value);
+            }
+            "Provides" => current.provides.extend(parse_dependency_list(value, &pkgver_regexp).into_iter().flatten()),
+            _ => match ChecksumType::from_field(key) {
+                Some(algo) => current.checksums.push((algo, String::from(value))),
+                None => current.metadata.add_field(key, value)
+            }
+        }
+    }
+    Ok(stanzas)
+}
 
-        if let Ok(lines) = read_lines(filename) {
-            let mut current_package_num = 0;
-            for line in lines {
-                if let Ok(ip) = line {
-                    // do more things with ip
-                    if kv_regexp.is_match(&ip) {
-                        match kv_regexp.captures(&ip) {
-                            None => {}
-                            Some(caps) => {
-                                let (key, value) = (
-                                    caps.name("key").unwrap().as_str(),
-                                    caps.name("value").unwrap().as_str()
-                                );
-                                match key {
-                                    "Package" => {
-                                        current_package_num = self.get_package_num_inserting(&value);
-                                    },
-                                    "Version" => {
-                                        let debver = value.trim().parse::<debversion::DebianVersionNum>().unwrap();
-                                        self.available_debvers.insert(current_package_num, debver); // Assume we always receive Package line before the Version line
-                                    },
-                                    "MD5sum" => {
-                                        self.md5sums.insert(current_package_num, String::from(value));
-                                    },
-                                    "Depends" => {
-                                        let dependencies = value.split(",");
-                                        let mut dependencies_vec = Vec::new();
-                                        for dependency in dependencies {
-                                            let alternatives = dependency.split("|");
-                                            let mut alternatives_vec = Vec::new();
-                                            for alternative in alternatives {
-                                                match pkgver_regexp.captures(alternative) {
-                                                    None => {}
-                                                    Some(caps) => {
-                                                        // Assume that regex capture will always have "pkg", but not necessarily have "op" and "ver".
-                                                        let package_num = self.get_package_num_inserting(caps.name("pkg").unwrap().as_str());
-                                                        let mut rel_version = Option::None;
-                                                        match caps.name("op") {
-                                                            None => {}
-                                                            Some(op) => {
-                                                                let op: debversion::VersionRelation = op.as_str().parse::<debversion::VersionRelation>().unwrap();
-                                                                // Assume that if regex captures on op, we will capture ver as well.
-                                                                let ver: String = caps.name("ver").unwrap().as_str().to_string();
-                                                                rel_version = Option::Some((op, ver));
-                                                            }
-                                                        }
-                                                        let rel_versioned_package_num = RelVersionedPackageNum {
-                                                            package_num,
-                                                            rel_version
-                                                        };
-                                                        alternatives_vec.push(rel_versioned_package_num);
-                                                    }
-                                                }
-                                            }
-                                            dependencies_vec.push(alternatives_vec);
-                                        }
-                                        self.dependencies.insert(current_package_num, dependencies_vec);
-                                    },
-                                    _ => {}
-                                }
-                            }
+/// Parses a comma-separated list of dependencies, each of which may be a |-separated list of alternatives.
+fn parse_dependency_list(value: &str, pkgver_regexp: &Regex) -> Vec<Vec<ParsedAlternative>> {
+    let dependencies = value.split(",");
+    let mut dependencies_vec = Vec::new();
+    for dependency in dependencies {
+        let alternatives = dependency.split("|");
+        let mut alternatives_vec = Vec::new();
+        for alternative in alternatives {
+            match pkgver_regexp.captures(alternative) {
+                None => {}
+                Some(caps) => {
+                    // Assume that regex capture will always have "pkg", but not necessarily have "op" and "ver".
+                    let package = String::from(caps.name("pkg").unwrap().as_str());
+                    let mut rel_version = Option::None;
+                    match caps.name("op") {
+                        None => {}
+                        Some(op) => {
+                            // check the relation now, so that the cache only ever holds valid ones
+                            let op: debversion::VersionRelation = op.as_str().parse::<debversion::VersionRelation>().unwrap();
+                            // Assume that if regex captures on op, we will capture ver as well.
+                            let ver: String = caps.name("ver").unwrap().as_str().to_string();
+                            rel_version = Option::Some((op.to_string(), ver));
                         }
                     }
+                    alternatives_vec.push(ParsedAlternative { package, rel_version });
                 }
             }
         }
-        println!("Packages available: {}", self.available_debvers.keys().len());
+        dependencies_vec.push(alternatives_vec);
     }
+    dependencies_vec
 }
 
 
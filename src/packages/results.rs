@@ -0,0 +1,477 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::packages::checksums::ChecksumType;
+use crate::packages::metadata::PackageMetadata;
+
+#[derive(Serialize)]
+pub struct InfoResult {
+    pub package: String,
+    pub version: Option<String>,
+    pub md5sum: Option<String>,
+    pub sha256: Option<String>,
+    pub sha512: Option<String>,
+    pub depends: Option<Vec<String>>,
+    pub installed_version: Option<String>,
+    pub newer_available: Option<bool>,
+    #[serde(flatten)]
+    pub metadata: Option<PackageMetadata>,
+}
+
+#[derive(Serialize)]
+pub struct DepsResult {
+    pub package: String,
+    pub depends: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DependencyStatus {
+    pub dependency: String,
+    pub satisfied_by: Option<String>,
+    pub installed_version: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DepsAvailableResult {
+    pub package: String,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+/// An installed package broken by a missing dependency or a conflicting package.
+#[derive(Serialize)]
+pub struct BrokenPackage {
+    pub package: String,
+    pub version: String,
+    /// the Depends or Conflicts clause that is broken, with its alternatives
+    pub clause: String,
+    /// for a conflict, the installed version of the conflicting package
+    pub conflicting_version: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BrokenTarget {
+    /// the missing dependency (with its alternatives) or the conflicting package
+    pub target: String,
+    /// the installed packages broken by target
+    pub packages: Vec<BrokenPackage>,
+}
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    pub unmet: Vec<BrokenTarget>,
+    pub conflicting: Vec<BrokenTarget>,
+    pub broken_packages: usize,
+    pub problems: usize,
+}
+
+/// Why one alternative of a dependency can't be used to install a package.
+#[derive(Serialize)]
+#[serde(tag = "problem", rename_all = "kebab-case")]
+pub enum AlternativeProblem {
+    /// the package isn't in the repository and nothing provides it
+    Missing { package: String },
+    /// the name is provided by some packages, but none of them satisfy the version requirement
+    NoProvider { package: String },
+    /// the package is available, but not at a version satisfying the version requirement
+    WrongVersion { package: String, version: String, required: String },
+    /// the package (or a provider of it) is available, but not installable itself;
+    /// reason is None if it was already explained higher up
+    Uninstallable { package: String, version: String, reason: Option<Box<UninstallableReason>> },
+}
+
+/// The dependency that makes a package uninstallable, with the problem with each of its alternatives.
+#[derive(Serialize)]
+pub struct UninstallableReason {
+    pub dependency: String,
+    pub problems: Vec<AlternativeProblem>,
+}
+
+#[derive(Serialize)]
+pub struct UninstallablePackage {
+    pub package: String,
+    pub version: String,
+    pub reason: UninstallableReason,
+}
+
+#[derive(Serialize)]
+pub struct InstallabilityResult {
+    pub uninstallable: Vec<UninstallablePackage>,
+    /// the number of available packages that were checked
+    pub checked: usize,
+}
+
+#[derive(Serialize)]
+pub struct TransitiveDepSolutionResult {
+    pub package: String,
+    pub solution: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedInstall {
+    pub package: String,
+    pub version: Option<String>,
+    /// the version being upgraded from, if any
+    pub installed_version: Option<String>,
+    /// in bytes; None if the index didn't give a Size
+    pub download_size: Option<u64>,
+    /// the change in disk usage, in bytes; None if an Installed-Size is missing
+    pub installed_size_delta: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct HowToInstallResult {
+    pub package: String,
+    pub to_install: Vec<String>,
+    /// the package itself (unless it's up to date) followed by to_install, with their sizes
+    pub plan: Vec<PlannedInstall>,
+    pub total_download_size: u64,
+    pub total_installed_size_delta: i64,
+    /// how many packages in the plan have a size missing from the totals
+    pub unknown_sizes: usize,
+}
+
+#[derive(Serialize)]
+pub struct SearchMatch {
+    pub package: String,
+    pub version: Option<String>,
+    pub installed_version: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    /// the number of matching packages, which may be more than the results if a limit was given
+    pub total: usize,
+    pub results: Vec<SearchMatch>,
+}
+
+#[derive(Serialize)]
+pub struct DiffedPackage {
+    pub package: String,
+    pub version: String,
+}
+
+/// Which component of a Debian version number [epoch:]upstream[-revision] changed; the most significant one if several did.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionChangeKind {
+    Epoch,
+    Upstream,
+    DebianRevision,
+}
+
+impl fmt::Display for VersionChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionChangeKind::Epoch => write!(f, "epoch"),
+            VersionChangeKind::Upstream => write!(f, "upstream"),
+            VersionChangeKind::DebianRevision => write!(f, "debian revision")
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionChange {
+    pub package: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub kind: VersionChangeKind,
+    /// whether the new version is lower than the old one
+    pub downgrade: bool,
+}
+
+#[derive(Serialize)]
+pub struct DependencyChange {
+    pub package: String,
+    /// dependencies (with all their alternatives) only in the new index
+    pub added: Vec<String>,
+    /// dependencies only in the old index
+    pub removed: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DiffPackagesResult {
+    pub old: String,
+    pub new: String,
+    pub added: Vec<DiffedPackage>,
+    pub removed: Vec<DiffedPackage>,
+    pub version_changes: Vec<VersionChange>,
+    /// only for packages in both indices
+    pub dependency_changes: Vec<DependencyChange>,
+}
+
+#[derive(Serialize)]
+pub struct QueuedVerification {
+    pub package: String,
+    pub version: String,
+    pub url: String,
+    /// whether the result will come from the verification cache rather than the server
+    pub cached: bool,
+}
+
+/// How a verification request turned out.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerificationOutcome {
+    /// the server's checksum is the same as the local one
+    Match,
+    /// the server's checksum is different
+    Mismatch,
+    /// the server answered with a status other than 200, e.g. 404 for an unknown version
+    HttpError,
+    /// there was no answer at all, e.g. the connection was refused or timed out
+    TransportError,
+    /// the request was cancelled before it got a final answer
+    Cancelled,
+}
+
+#[derive(Clone, Serialize)]
+pub struct VerificationResult {
+    pub package: String,
+    pub version: String,
+    /// the URL requested from the checksum server
+    pub url: String,
+    /// the server that answered
+    pub server: String,
+    /// 0 if there was no response
+    pub http_status: u32,
+    /// the type of checksum asked for last, the strongest one that the server had
+    pub algo: ChecksumType,
+    /// the checksum returned by the server, if it answered with 200
+    pub remote_hash: Option<String>,
+    pub local_hash: String,
+    pub outcome: VerificationOutcome,
+    /// whether another server has returned a different checksum for this package version
+    pub suspicious: bool,
+    pub conflicting_server: Option<String>,
+    pub conflicting_hash: Option<String>,
+    /// what went wrong, for a transport error
+    pub error: Option<String>,
+    /// whether the server's checksum came from the verification cache rather than a request
+    pub cached: bool,
+    /// 1 unless the request was retried or a weaker checksum was asked for, 0 if the result was cached
+    pub attempts: u32,
+    /// from sending the request to receiving the whole response, for the last attempt
+    pub latency_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct VerificationReport {
+    pub results: Vec<VerificationResult>,
+}
+
+impl VerificationResult {
+    /// Whether the hash matched and no server disagreed.
+    pub fn is_clean(&self) -> bool {
+        self.outcome == VerificationOutcome::Match && !self.suspicious
+    }
+}
+
+/// The outcome of verifying many packages at once.
+#[derive(Serialize)]
+pub struct VerificationSummary {
+    pub matches: usize,
+    pub mismatches: usize,
+    pub http_errors: usize,
+    pub transport_errors: usize,
+    pub cancelled: usize,
+    /// results where servers disagreed, whatever their outcome
+    pub suspicious: usize,
+    /// results answered from the verification cache
+    pub cached: usize,
+    /// packages that weren't verified because no checksum is known for them, or for an installed package, for its installed version
+    pub skipped: Vec<String>,
+    pub elapsed_ms: f64,
+    pub results: Vec<VerificationResult>,
+}
+
+impl VerificationSummary {
+    pub fn new(results: Vec<VerificationResult>, skipped: Vec<String>, elapsed: std::time::Duration) -> VerificationSummary {
+        let count = |outcome| results.iter().filter(|r| r.outcome == outcome).count();
+        VerificationSummary {
+            matches: count(VerificationOutcome::Match),
+            mismatches: count(VerificationOutcome::Mismatch),
+            http_errors: count(VerificationOutcome::HttpError),
+            transport_errors: count(VerificationOutcome::TransportError),
+            cancelled: count(VerificationOutcome::Cancelled),
+            suspicious: results.iter().filter(|r| r.suspicious).count(),
+            cached: results.iter().filter(|r| r.cached).count(),
+            skipped,
+            elapsed_ms: elapsed.as_micros() as f64 / 1000.0,
+            results,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ServerStatus {
+    pub server: String,
+    /// false if its last requests failed and it isn't being sent new requests for now
+    pub up: bool,
+    pub requests: usize,
+    /// requests that got a response other than 429 or 5xx
+    pub successes: usize,
+    pub consecutive_failures: u32,
+    /// of the successful requests
+    pub mean_latency_ms: Option<f64>,
+    /// the kind of credentials sent to it, if any
+    pub auth: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ServersResult {
+    pub servers: Vec<ServerStatus>,
+}
+
+/// Where a background job is.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    Running,
+    /// cancelled, but still removing its requests
+    Cancelling,
+    /// its results are ready to be waited for
+    Finished,
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            JobState::Running => "running",
+            JobState::Cancelling => "cancelling",
+            JobState::Finished => "finished",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobStatus {
+    pub id: usize,
+    pub state: JobState,
+    /// requests with a final result
+    pub done: usize,
+    pub total: usize,
+    pub mismatches: usize,
+    /// HTTP and transport errors
+    pub errors: usize,
+    pub elapsed_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct JobsResult {
+    pub jobs: Vec<JobStatus>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyCacheStats {
+    /// None if there is no cache directory, and hashes are only cached for the session
+    pub path: Option<String>,
+    /// of the cache file, if there is one
+    pub size: Option<u64>,
+    pub ttl_secs: u64,
+    pub entries: usize,
+    pub fresh: usize,
+    /// lookups this session
+    pub hits: usize,
+    pub misses: usize,
+}
+
+#[derive(Serialize)]
+pub struct VerifyCacheClearResult {
+    pub removed: usize,
+}
+
+/// What a checksum server has answered so far.
+#[derive(Serialize)]
+pub struct ChecksumServerStats {
+    pub addr: String,
+    pub requests: usize,
+    /// answered with the right checksum
+    pub served: usize,
+    pub wrong_hashes: usize,
+    pub injected_errors: usize,
+    pub not_found: usize,
+}
+
+#[derive(Serialize)]
+pub struct CacheEntry {
+    pub path: String,
+    pub size: u64,
+    pub kind: Option<String>,
+    pub source: Option<String>,
+    /// whether the source file is unchanged since it was cached
+    pub valid: bool,
+}
+
+#[derive(Serialize)]
+pub struct CacheInfoResult {
+    /// None if caching is turned off
+    pub cache_dir: Option<String>,
+    pub format_version: u32,
+    pub entries: Vec<CacheEntry>,
+}
+
+#[derive(Serialize)]
+pub struct CacheClearResult {
+    pub removed: usize,
+}
+
+/// How a .deb file compares with the loaded index.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebOutcome {
+    /// its size and checksums are the ones in the index
+    Ok,
+    /// it's in the index, but its size or a checksum is different, or it couldn't be read
+    Corrupt,
+    /// it isn't in the index, by its file name or by the package and version in its control data
+    Unknown,
+    /// the index has a Filename for it, but there was no such file
+    Missing,
+}
+
+/// How a .deb file was found in the index.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebMatch {
+    /// by the Filename field
+    Filename,
+    /// by the Package and Version in the control file inside it
+    Control,
+}
+
+#[derive(Serialize)]
+pub struct DebVerification {
+    /// None for a missing file
+    pub path: Option<String>,
+    /// from the index, or from the control data of an unknown file
+    pub package: Option<String>,
+    pub version: Option<String>,
+    /// the Filename field of the package in the index
+    pub filename: Option<String>,
+    pub matched_by: Option<DebMatch>,
+    pub outcome: DebOutcome,
+    pub size: Option<u64>,
+    pub expected_size: Option<u64>,
+    /// the checksums that were compared, and those of them that differ
+    pub checked: Vec<ChecksumType>,
+    pub mismatched: Vec<ChecksumType>,
+    /// why the file couldn't be read or isn't a .deb
+    pub error: Option<String>,
+}
+
+/// The outcome of verifying a directory of .deb files.
+#[derive(Serialize)]
+pub struct DebPoolReport {
+    pub dir: String,
+    pub files: usize,
+    pub ok: usize,
+    pub corrupt: usize,
+    pub unknown: usize,
+    pub missing: usize,
+    /// the bytes hashed
+    pub bytes: u64,
+    pub elapsed_ms: f64,
+    pub results: Vec<DebVerification>,
+}
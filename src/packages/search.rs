@@ -0,0 +1,179 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use crate::Packages;
+use crate::packages::interner::PackageId;
+use crate::packages::{invert_provides, ProvidesByProvider};
+use crate::packages::metadata::Priority;
+use crate::packages::results::{SearchMatch, SearchResult};
+
+/// One term of a search query; a package matches the query if it matches all of its terms.
+enum Predicate {
+    /// a bare regex, matched against the name and the synopsis
+    NameOrDescription(Regex),
+    Name(Regex),
+    /// matched against the synopsis and the long description
+    Description(Regex),
+    Section(Regex),
+    Priority(Priority),
+    Installed(bool),
+    Available(bool),
+    /// the installed version is older than the available one
+    Upgradable(bool),
+    /// some alternative of some dependency of the available or installed version has a name matching the glob
+    Depends(Regex),
+    /// the available or installed version provides a name matching the glob
+    Provides(Regex),
+}
+
+#[derive(Clone, Copy)]
+enum SortKey {
+    Name,
+    Version,
+    InstalledVersion,
+}
+
+/// Turns a shell-style glob (with * and ?) into an anchored regex.
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let escaped = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+    Regex::new(&format!("^{}$", escaped))
+}
+
+fn parse_yes_no(field: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("{} must be yes or no, not {:?}", field, value))
+    }
+}
+
+fn parse_predicate(term: &str) -> Result<Predicate, String> {
+    let (field, value) = match term.split_once(':') {
+        None => return Regex::new(term).map(Predicate::NameOrDescription).map_err(|e| e.to_string()),
+        Some(split) => split
+    };
+    match field {
+        "name" => Regex::new(value).map(Predicate::Name).map_err(|e| e.to_string()),
+        "description" => Regex::new(value).map(Predicate::Description).map_err(|e| e.to_string()),
+        "section" => glob_to_regex(value).map(Predicate::Section).map_err(|e| e.to_string()),
+        "priority" => value.parse().map(Predicate::Priority),
+        "installed" => parse_yes_no(field, value).map(Predicate::Installed),
+        "available" => parse_yes_no(field, value).map(Predicate::Available),
+        "upgradable" => parse_yes_no(field, value).map(Predicate::Upgradable),
+        "depends" => glob_to_regex(value).map(Predicate::Depends).map_err(|e| e.to_string()),
+        "provides" => glob_to_regex(value).map(Predicate::Provides).map_err(|e| e.to_string()),
+        _ => Err(format!("unknown search field {:?}", field))
+    }
+}
+
+impl Packages {
+    /// Lists the packages matching all the terms of the query, sorted by sort_key (name, version or installed;
+    /// prefix with - to reverse) and limited to the first limit results.
+    pub fn search(&self, query: &[&str], sort_key: Option<&str>, limit: Option<usize>) -> Result<SearchResult, String> {
+        let predicates : Vec<Predicate> = query.iter().map(|term| parse_predicate(term)).collect::<Result<_, _>>()?;
+        let (sort_key, reverse) = match sort_key.map(|k| k.strip_prefix('-').map_or((k, false), |k| (k, true))) {
+            None => (SortKey::Name, false),
+            Some(("name", reverse)) => (SortKey::Name, reverse),
+            Some(("version", reverse)) => (SortKey::Version, reverse),
+            Some(("installed", reverse)) => (SortKey::InstalledVersion, reverse),
+            Some((k, _)) => return Err(format!("can't sort by {:?}, expected name, version or installed", k))
+        };
+
+        // what each package provides, available and installed, if the query asks
+        let provides = match predicates.iter().any(|p| matches!(p, Predicate::Provides(_))) {
+            true => vec![invert_provides(&self.available_provides), invert_provides(&self.installed_provides)],
+            false => vec![]
+        };
+        let mut matches : Vec<PackageId> = self.interner.ids()
+            // leave out names that are only mentioned as dependencies
+            .filter(|pn| self.available_debvers.contains_key(*pn) || self.installed_debvers.contains_key(*pn))
+            .filter(|pn| predicates.iter().all(|p| self.matches_predicate(*pn, p, &provides)))
+            .collect();
+        matches.sort_unstable_by(|a, b| {
+            let ordering = match sort_key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Version => self.available_debvers.get(*a).cmp(&self.available_debvers.get(*b)),
+                SortKey::InstalledVersion => self.installed_debvers.get(*a).cmp(&self.installed_debvers.get(*b))
+            }.then_with(|| self.get_package_name(*a).cmp(self.get_package_name(*b)));
+            if reverse { ordering.reverse() } else { ordering }
+        });
+
+        let total = matches.len();
+        Ok(SearchResult {
+            total,
+            results: matches.into_iter().take(limit.unwrap_or(total)).map(|pn| SearchMatch {
+                package: String::from(self.get_package_name(pn)),
+                version: self.available_debvers.get(pn).map(|v| v.to_string()),
+                installed_version: self.installed_debvers.get(pn).map(|v| v.to_string()),
+            }).collect(),
+        })
+    }
+
+    fn matches_predicate(&self, package_num: PackageId, predicate: &Predicate, provides: &[ProvidesByProvider]) -> bool {
+        match predicate {
+            Predicate::NameOrDescription(r) => r.is_match(self.get_package_name(package_num))
+                || self.metadata(package_num).and_then(|m| m.description.as_ref()).is_some_and(|d| r.is_match(d)),
+            Predicate::Name(r) => r.is_match(self.get_package_name(package_num)),
+            Predicate::Description(r) => self.metadata(package_num).is_some_and(|m| {
+                m.description.iter().chain(m.long_description.iter()).any(|d| r.is_match(d))
+            }),
+            Predicate::Section(r) => self.metadata(package_num).and_then(|m| m.section.as_ref()).is_some_and(|s| r.is_match(s)),
+            Predicate::Priority(p) => self.metadata(package_num).and_then(|m| m.priority) == Some(*p),
+            Predicate::Installed(b) => self.installed_debvers.contains_key(package_num) == *b,
+            Predicate::Available(b) => self.available_debvers.contains_key(package_num) == *b,
+            Predicate::Upgradable(b) => {
+                let upgradable = match (self.available_debvers.get(package_num), self.installed_debvers.get(package_num)) {
+                    (Some(a), Some(i)) => a > i,
+                    _ => false
+                };
+                upgradable == *b
+            }
+            Predicate::Depends(r) => self.dependencies.get(package_num).into_iter().chain(self.installed_dependencies.get(package_num))
+                .flatten().flatten().any(|d| r.is_match(self.get_package_name(d.package_num))),
+            Predicate::Provides(r) => provides.iter().filter_map(|p| p.get(package_num)).flatten()
+                .any(|(provided, _)| r.is_match(self.get_package_name(*provided))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::tests::TestDir;
+
+    fn search_names(packages: &Packages, query: &[&str]) -> Vec<String> {
+        packages.search(query, None, None).unwrap().results.into_iter().map(|m| m.package).collect()
+    }
+
+    #[test]
+    fn depends_and_provides_look_at_available_and_installed_versions() {
+        let dir = TestDir::new("search");
+        let available = dir.write("Packages", "\
+Package: exim4
+Version: 4.95
+Provides: mail-transport-agent
+Depends: libssl3
+
+Package: curl
+Version: 7.81
+Depends: libcurl4 (= 7.81)
+");
+        // an installed package that isn't available any more
+        let status = dir.write("status", "\
+Package: postfix
+Status: install ok installed
+Version: 3.5
+Provides: mail-transport-agent
+Depends: libssl1.1
+");
+        let mut packages = Packages::new();
+        packages.parse_packages(&available).unwrap();
+        packages.parse_installed(&status).unwrap();
+
+        assert_eq!(search_names(&packages, &["provides:mail-transport-*"]), vec!["exim4", "postfix"]);
+        assert_eq!(search_names(&packages, &["depends:libssl*"]), vec!["exim4", "postfix"]);
+        assert_eq!(search_names(&packages, &["depends:libcurl?"]), vec!["curl"]);
+        assert!(search_names(&packages, &["provides:mail"]).is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Packages;
+use crate::packages::cache::write_cache_file;
+use crate::packages::checksums::ChecksumType;
+use crate::packages::results::{VerifyCacheClearResult, VerifyCacheStats};
+
+const VERIFY_CACHE_MAGIC : &[u8; 8] = b"RPKGVRFY";
+/// Bump this whenever the layout of the cached hashes changes; a cache file with another version is ignored.
+const VERIFY_CACHE_FORMAT_VERSION : u32 = 2;
+// not a .cache file, so that cache-clear leaves it alone
+const VERIFY_CACHE_FILE : &str = "verifications.bin";
+
+#[derive(Serialize, Deserialize)]
+struct CachedHash {
+    hash : String,
+    // seconds since the epoch
+    fetched_at : u64,
+}
+
+type CacheEntries = HashMap<(String, String, String, ChecksumType), CachedHash>;
+
+/// The hashes returned by checksum servers, keyed by server, package, version and checksum type; loaded from the cache directory
+/// the first time it's needed, and written back after execute() if anything was added.
+#[derive(Default)]
+pub struct VerifyCache {
+    loaded : bool,
+    entries : CacheEntries,
+    dirty : bool,
+    hits : usize,
+    misses : usize,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn is_fresh(entry: &CachedHash, ttl: Duration, now: u64) -> bool {
+    entry.fetched_at.saturating_add(ttl.as_secs()) > now
+}
+
+/// The hashes in the cache file at path, or None if there isn't one of this version.
+fn read_verify_cache_file(path: &Path) -> Option<CacheEntries> {
+    let bytes = fs::read(path).ok()?;
+    let (magic, rest) = (bytes.get(..8)?, bytes.get(8..)?);
+    let (version, rest) = (rest.get(..4)?, rest.get(4..)?);
+    if magic != VERIFY_CACHE_MAGIC || version != VERIFY_CACHE_FORMAT_VERSION.to_le_bytes() {
+        return None;
+    }
+    bincode::deserialize(rest).ok()
+}
+
+impl Packages {
+    fn verify_cache_path(&self) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| dir.join(VERIFY_CACHE_FILE))
+    }
+
+    fn load_verify_cache(&mut self) {
+        if self.async_state.verify_cache.loaded {
+            return;
+        }
+        let entries = self.verify_cache_path().and_then(|path| read_verify_cache_file(&path));
+        let cache = &mut self.async_state.verify_cache;
+        cache.entries = entries.unwrap_or_default();
+        cache.loaded = true;
+    }
+
+    /// A fresh hash for package at version from one of the current servers, with that server and its type, if there is one;
+    /// the types are tried in the order of algos.
+    pub(super) fn cached_hash(&mut self, package: &str, version: &str, algos: &[ChecksumType]) -> Option<(String, ChecksumType, String)> {
+        let ttl = self.async_state.policy.cache_ttl;
+        if ttl.is_zero() {
+            return None;
+        }
+        self.load_verify_cache();
+        let now = now_secs();
+        let state = &mut self.async_state;
+        let mirrors = state.mirrors.lock().unwrap();
+        let found = algos.iter().find_map(|algo| mirrors.addrs().find_map(|server| {
+            let key = (String::from(server), String::from(package), String::from(version), *algo);
+            state.verify_cache.entries.get(&key).filter(|e| is_fresh(e, ttl, now)).map(|e| (String::from(server), *algo, e.hash.clone()))
+        }));
+        if found.is_some() { state.verify_cache.hits += 1 } else { state.verify_cache.misses += 1 }
+        found
+    }
+
+    /// Remembers the algo hash server returned for package at version, unless caching is off.
+    pub(super) fn remember_hash(&mut self, server: &str, package: &str, version: &str, algo: ChecksumType, hash: &str) {
+        if self.async_state.policy.cache_ttl.is_zero() {
+            return;
+        }
+        self.load_verify_cache();
+        let cache = &mut self.async_state.verify_cache;
+        cache.entries.insert((String::from(server), String::from(package), String::from(version), algo),
+                             CachedHash { hash: String::from(hash), fetched_at: now_secs() });
+        cache.dirty = true;
+    }
+
+    /// Writes the cache back without the expired entries, if anything was added. What other sessions wrote to the
+    /// file since it was loaded is merged in first, keeping the newer hash where both have one, though two sessions
+    /// saving at the same moment can still lose each other's latest hashes. Like the parse cache, this is
+    /// best-effort: failures are ignored, and verify-cache stats shows whether there is a cache file.
+    pub(super) fn save_verify_cache(&mut self) {
+        let path = match self.verify_cache_path() {
+            Some(path) if self.async_state.verify_cache.dirty => path,
+            _ => return
+        };
+        let (ttl, now) = (self.async_state.policy.cache_ttl, now_secs());
+        let cache = &mut self.async_state.verify_cache;
+        for (key, on_disk) in read_verify_cache_file(&path).unwrap_or_default() {
+            match cache.entries.get(&key) {
+                Some(entry) if entry.fetched_at >= on_disk.fetched_at => (),
+                _ => { cache.entries.insert(key, on_disk); }
+            }
+        }
+        cache.entries.retain(|_, e| is_fresh(e, ttl, now));
+        cache.dirty = false;
+        write_cache_file(&path, VERIFY_CACHE_MAGIC, VERIFY_CACHE_FORMAT_VERSION, |writer| bincode::serialize_into(writer, &cache.entries));
+    }
+
+    /// How many hashes are cached and how many of them are fresh, and how many lookups were answered this session.
+    pub fn verify_cache_stats(&mut self) -> VerifyCacheStats {
+        self.load_verify_cache();
+        let path = self.verify_cache_path();
+        let (ttl, now) = (self.async_state.policy.cache_ttl, now_secs());
+        let cache = &self.async_state.verify_cache;
+        VerifyCacheStats {
+            size: path.as_ref().and_then(|p| fs::metadata(p).ok()).map(|m| m.len()),
+            path: path.map(|p| p.to_string_lossy().into_owned()),
+            ttl_secs: ttl.as_secs(),
+            entries: cache.entries.len(),
+            fresh: cache.entries.values().filter(|e| is_fresh(e, ttl, now)).count(),
+            hits: cache.hits,
+            misses: cache.misses,
+        }
+    }
+
+    /// Forgets all the cached hashes and removes the cache file.
+    pub fn verify_cache_clear(&mut self) -> Result<VerifyCacheClearResult, String> {
+        self.load_verify_cache();
+        let cache = &mut self.async_state.verify_cache;
+        let removed = cache.entries.len();
+        cache.entries.clear();
+        cache.dirty = false;
+        if let Some(path) = self.verify_cache_path() {
+            match fs::remove_file(&path) {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(format!("couldn't remove {}: {}", path.display(), e))
+            }
+        }
+        Ok(VerifyCacheClearResult { removed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages::tests::TestDir;
+
+    #[test]
+    fn remembered_hashes_survive_a_restart() {
+        let dir = TestDir::new("verify-cache");
+        let new_packages = || {
+            let mut packages = Packages::new();
+            packages.set_cache_dir(Some(dir.path().to_path_buf()));
+            packages.set_servers(&["mirror:4590"]);
+            packages
+        };
+        let mut packages = new_packages();
+        packages.remember_hash("mirror:4590", "foo", "1.0", ChecksumType::Sha256, "abc");
+        packages.save_verify_cache();
+
+        let mut packages = new_packages();
+        assert_eq!(packages.cached_hash("foo", "1.0", &[ChecksumType::Sha256, ChecksumType::Md5]),
+                   Some((String::from("mirror:4590"), ChecksumType::Sha256, String::from("abc"))));
+        assert_eq!(packages.cached_hash("foo", "1.0", &[ChecksumType::Md5]), None);
+        assert_eq!(packages.cached_hash("foo", "1.1", &[ChecksumType::Sha256]), None);
+        let stats = packages.verify_cache_stats();
+        assert_eq!((stats.entries, stats.fresh, stats.hits, stats.misses), (1, 1, 1, 2));
+
+        // only hashes from the current servers count
+        packages.set_servers(&["other:4590"]);
+        assert_eq!(packages.cached_hash("foo", "1.0", &[ChecksumType::Sha256]), None);
+    }
+
+    #[test]
+    fn sessions_sharing_the_cache_keep_each_others_hashes() {
+        let dir = TestDir::new("verify-cache-merge");
+        let new_packages = || {
+            let mut packages = Packages::new();
+            packages.set_cache_dir(Some(dir.path().to_path_buf()));
+            packages.set_servers(&["mirror:4590"]);
+            packages
+        };
+        let (mut first, mut second) = (new_packages(), new_packages());
+        first.remember_hash("mirror:4590", "foo", "1.0", ChecksumType::Md5, "abc");
+        second.remember_hash("mirror:4590", "bar", "2.0", ChecksumType::Md5, "def");
+        first.save_verify_cache();
+        second.save_verify_cache();
+
+        let mut packages = new_packages();
+        assert_eq!(packages.cached_hash("foo", "1.0", &[ChecksumType::Md5]).map(|(_, _, hash)| hash), Some(String::from("abc")));
+        assert_eq!(packages.cached_hash("bar", "2.0", &[ChecksumType::Md5]).map(|(_, _, hash)| hash), Some(String::from("def")));
+    }
+
+    #[test]
+    fn hashes_expire_after_the_ttl() {
+        let entry = CachedHash { hash: String::from("abc"), fetched_at: 1000 };
+        assert!(is_fresh(&entry, Duration::from_secs(60), 1059));
+        assert!(!is_fresh(&entry, Duration::from_secs(60), 1060));
+    }
+}
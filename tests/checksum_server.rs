@@ -0,0 +1,115 @@
+// The verifier against the checksum server of serve_checksums, over localhost.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rpkg::packages::checksums::ChecksumType;
+use rpkg::packages::results::{VerificationOutcome, VerificationResult};
+use rpkg::packages::{ServeOptions, VerifyPolicy};
+use rpkg::Packages;
+
+const CSV : &str = "\
+name,version,hash,sha256
+foo,1.0,1e2695355cb6b6b27d5ea9888e057602,
+bar,2.0-1+b1,7b9c9f0ff1ed8e30d6a1b3ce0b56ad51,5f2b3d1c8bd0ccfa1b2e6ec2e06eb0e6fb1b23a0c3d1b3e01d4b67e5b4d2fbbb
+baz,1:3~rc1,0d7e5c61c4e4a6c6bbd50eb2b4d97f69,
+";
+
+fn test_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rpkg-checksum-server-{}-{}", std::process::id(), test));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Packages loaded from CSV, without proxies.
+fn packages(test: &str) -> Packages {
+    let dir = test_dir(test);
+    let csv = dir.join("packages.csv");
+    std::fs::write(&csv, CSV).unwrap();
+    let mut packages = Packages::new();
+    packages.parse_csv(csv.to_str().unwrap()).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    packages.set_verify_policy(VerifyPolicy {
+        max_retries : 0,
+        cache_ttl : Duration::ZERO,
+        proxy : Some(String::new()),
+        ..VerifyPolicy::default()
+    });
+    packages
+}
+
+fn verify_all(packages: &mut Packages) -> Vec<VerificationResult> {
+    for package in ["foo", "bar", "baz"] {
+        packages.enq_verify(package, false).unwrap();
+    }
+    let mut results = packages.execute();
+    results.sort_by(|a, b| a.package.cmp(&b.package));
+    results
+}
+
+#[test]
+fn checksums_served_match() {
+    let mut packages = packages("match");
+    let server = packages.serve_checksums("127.0.0.1:0", ServeOptions::default()).unwrap();
+    assert_eq!(server.checksums(), 3);
+    packages.set_server(&server.addr());
+
+    let results = verify_all(&mut packages);
+    assert!(results.iter().all(|r| matches!(r.outcome, VerificationOutcome::Match)));
+    // the strongest checksum known is asked for
+    let algos = results.iter().map(|r| (r.package.as_str(), r.algo)).collect::<Vec<_>>();
+    assert_eq!(algos, vec![("bar", ChecksumType::Sha256), ("baz", ChecksumType::Md5), ("foo", ChecksumType::Md5)]);
+    assert!(results[1].url.ends_with("/rest/v1/checksums/baz/1%3A3~rc1"), "{}", results[1].url);
+
+    let stats = server.stop();
+    assert_eq!((stats.requests, stats.served, stats.not_found), (3, 3, 0));
+}
+
+#[test]
+fn unknown_versions_are_not_found() {
+    let mut packages = packages("not-found");
+    let server = packages.serve_checksums("127.0.0.1:0", ServeOptions::default()).unwrap();
+    packages.set_server(&server.addr());
+
+    packages.enq_verify_with_version("foo", "0.9", false).unwrap();
+    let result = packages.execute().pop().unwrap();
+    assert!(matches!(result.outcome, VerificationOutcome::HttpError));
+    assert_eq!(result.http_status, 404);
+    assert_eq!(server.stop().not_found, 1);
+}
+
+#[test]
+fn wrong_hashes_and_injected_errors_are_reported() {
+    let mut packages = packages("faults");
+    let options = ServeOptions { wrong_hash_rate: 1.0, ..ServeOptions::default() };
+    let server = packages.serve_checksums("127.0.0.1:0", options).unwrap();
+    packages.set_server(&server.addr());
+    assert!(verify_all(&mut packages).iter().all(|r| matches!(r.outcome, VerificationOutcome::Mismatch)));
+    assert_eq!(server.stop().wrong_hashes, 3);
+
+    let options = ServeOptions { error_rate: 1.0, error_status: 503, ..ServeOptions::default() };
+    let server = packages.serve_checksums("127.0.0.1:0", options).unwrap();
+    packages.set_server(&server.addr());
+    let results = verify_all(&mut packages);
+    assert!(results.iter().all(|r| matches!(r.outcome, VerificationOutcome::HttpError) && r.http_status == 503));
+    assert_eq!(server.stop().injected_errors, 3);
+}
+
+#[test]
+fn a_batch_run_verifies_against_serve_checksums_and_stops_serving() {
+    let dir = test_dir("batch");
+    let csv = dir.join("packages.csv");
+    std::fs::write(&csv, CSV).unwrap();
+    let commands = format!("load-csv {}; serve-checksums 127.0.0.1:0; enq-verify foo; enq-verify bar; execute", csv.display());
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rpkg"))
+        .args(["-c", &commands])
+        .env("RPKG_CACHE_DIR", "")
+        .env_remove("http_proxy").env_remove("HTTP_PROXY").env_remove("https_proxy").env_remove("HTTPS_PROXY")
+        .output().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    // without --keep-serving, the run ends with its input
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("verifying foo, matches: true"), "{}", stdout);
+    assert!(stdout.contains("verifying bar, matches: true"), "{}", stdout);
+}